@@ -33,7 +33,11 @@ export type IncomingMessageType =
   | { type: "TelegramMessage"; data: TelegramMessageData }
   | { type: "CallbackQuery"; data: CallbackQueryData }
   | { type: "MessageReaction"; data: MessageReactionData }
-  | { type: "EditedMessage"; data: EditedMessageData };
+  | { type: "EditedMessage"; data: EditedMessageData }
+  | { type: "Poll"; data: PollData }
+  | { type: "PollAnswer"; data: PollAnswerData }
+  | { type: "ChatJoinRequest"; data: ChatJoinRequestData }
+  | { type: "ChatMember"; data: ChatMemberData };
 
 /**
  * Data for incoming Telegram messages
@@ -43,6 +47,8 @@ export interface TelegramMessageData {
   message: TelegramMessage;
   /** File attachments with download URLs - files are not downloaded yet */
   file_attachments: FileInfo[];
+  /** Forum topic of the message; use it as MessageTarget.thread_id to reply in the same topic */
+  thread_id?: number;
 }
 
 /**
@@ -54,6 +60,7 @@ export interface CallbackQueryData {
   message_id: number;
   callback_data: string;
   callback_query_id: string;
+  thread_id?: number; // Forum topic of the message the button is attached to
 }
 
 /**
@@ -64,10 +71,15 @@ export interface MessageReactionData {
   message_id: number;
   user_id?: number; // undefined if anonymous
   date: string; // ISO 8601 datetime string
-  old_reaction: string[]; // emoji strings
-  new_reaction: string[]; // emoji strings
+  old_reaction: Reaction[];
+  new_reaction: Reaction[];
 }
 
+/**
+ * An emoji, "custom:<custom_emoji_id>" for a custom emoji, or "paid"
+ */
+export type Reaction = string;
+
 /**
  * Data for edited message events
  */
@@ -78,6 +90,79 @@ export interface EditedMessageData {
   file_attachments: FileInfo[];
   /** Edit date from Telegram (when the message was edited) */
   edit_date?: number; // Unix timestamp
+  /** Forum topic of the message */
+  thread_id?: number;
+}
+
+/**
+ * Current state of a poll sent by the bot, published when votes change or the
+ * poll is closed
+ */
+export interface PollData {
+  poll_id: string;
+  question: string;
+  options: PollOptionData[];
+  total_voter_count: number;
+  is_closed: boolean;
+  is_anonymous: boolean;
+  poll_type: "regular" | "quiz";
+  allows_multiple_answers: boolean;
+  correct_option_id?: number; // Quizzes, once closed
+}
+
+export interface PollOptionData {
+  text: string;
+  voter_count: number;
+}
+
+/**
+ * A vote in a non-anonymous poll sent by the bot
+ */
+export interface PollAnswerData {
+  poll_id: string;
+  user_id?: number; // undefined if the vote was cast on behalf of a chat
+  voter_chat_id?: number;
+  option_ids: number[]; // 0-based; empty when the vote was retracted
+}
+
+/**
+ * A user asking to join a chat in which the bot can approve members
+ */
+export interface ChatJoinRequestData {
+  chat_id: number;
+  user_id: number;
+  username?: string;
+  first_name: string;
+  last_name?: string;
+  user_chat_id: number; // Private chat with the user, open until the request is handled
+  bio?: string;
+  invite_link?: string;
+  date: string; // ISO 8601 datetime string
+}
+
+export type ChatMemberStatus =
+  | "creator"
+  | "administrator"
+  | "member"
+  | "restricted"
+  | "left"
+  | "kicked";
+
+/**
+ * A change of a member's status in a chat the bot administers
+ */
+export interface ChatMemberData {
+  chat_id: number;
+  user_id: number;
+  username?: string;
+  first_name: string;
+  changed_by: number; // The admin who made the change, or the member themselves
+  old_status: ChatMemberStatus;
+  new_status: ChatMemberStatus;
+  is_member: boolean; // Whether the user is in the chat after the change
+  invite_link?: string;
+  via_join_request: boolean;
+  date: string; // ISO 8601 datetime string
 }
 
 /**
@@ -333,8 +418,33 @@ export type OutgoingMessageType =
   | { type: "DocumentMessage"; data: DocumentMessageData }
   | { type: "StickerMessage"; data: StickerMessageData }
   | { type: "AnimationMessage"; data: AnimationMessageData }
+  | { type: "MediaGroup"; data: MediaGroupData }
+  | { type: "LocationMessage"; data: LocationMessageData }
+  | { type: "VenueMessage"; data: VenueMessageData }
+  | { type: "ContactMessage"; data: ContactMessageData }
+  | { type: "DiceMessage"; data: DiceMessageData }
+  | { type: "PollMessage"; data: PollMessageData }
+  | { type: "StopPoll"; data: StopPollData }
+  | { type: "StreamMessage"; data: StreamMessageData }
   | { type: "EditMessage"; data: EditMessageData }
+  | { type: "EditCaption"; data: EditCaptionData }
+  | { type: "EditMedia"; data: EditMediaData }
+  | { type: "EditLiveLocation"; data: EditLiveLocationData }
   | { type: "DeleteMessage"; data: DeleteMessageData }
+  | { type: "ForwardMessage"; data: ForwardMessageData }
+  | { type: "CopyMessage"; data: CopyMessageData }
+  | { type: "CopyMessages"; data: CopyMessagesData }
+  | { type: "PinChatMessage"; data: PinChatMessageData }
+  | { type: "UnpinChatMessage"; data: UnpinChatMessageData }
+  | { type: "UnpinAllChatMessages"; data: Record<string, never> }
+  | { type: "SetReaction"; data: SetReactionData }
+  | { type: "BanChatMember"; data: BanChatMemberData }
+  | { type: "UnbanChatMember"; data: UnbanChatMemberData }
+  | { type: "RestrictChatMember"; data: RestrictChatMemberData }
+  | { type: "PromoteChatMember"; data: PromoteChatMemberData }
+  | { type: "ApproveChatJoinRequest"; data: ChatJoinRequestDecisionData }
+  | { type: "DeclineChatJoinRequest"; data: ChatJoinRequestDecisionData }
+  | { type: "AnswerCallbackQuery"; data: AnswerCallbackQueryData }
   | { type: "TypingMessage"; data: TypingMessageData };
 
 /**
@@ -343,48 +453,65 @@ export type OutgoingMessageType =
 export interface MessageTarget {
   platform: string; // "telegram"
   chat_id: number;
-  thread_id?: number; // For forum groups
+  thread_id?: number; // Forum topic; applied to every send and chat action
 }
 
 // =============================================================================
 // OUTGOING MESSAGE DATA TYPES
 // =============================================================================
 
-export interface TextMessageData {
-  text: string;
+/**
+ * Delivery options accepted next to the other fields of every variant that
+ * sends a new message
+ */
+export interface SendOptions {
+  reply_to_message_id?: number; // Reply to this message in the target chat
+  quote?: string; // Part of the replied-to message to quote, verbatim
+  allow_sending_without_reply?: boolean; // Send even if the replied-to message is gone
+  disable_notification?: boolean; // Deliver silently
+  protect_content?: boolean; // Prevent forwarding and saving
+  message_effect_id?: string; // Private chats only
+}
+
+export interface TextMessageData extends SendOptions {
+  text: string; // Split into several messages past 4096 characters
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
   parse_mode?: string; // "HTML", "Markdown", etc.
   disable_web_page_preview?: boolean;
 }
 
-export interface ImageMessageData {
-  image_path: string;
+export interface ImageMessageData extends SendOptions {
+  image: MediaSource; // Legacy name: image_path
   caption?: string;
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
 }
 
-export interface AudioMessageData {
-  audio_path: string;
+export interface AudioMessageData extends SendOptions {
+  audio: MediaSource; // Legacy name: audio_path
   caption?: string;
   duration?: number;
   performer?: string;
   title?: string;
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
 }
 
-export interface VoiceMessageData {
-  voice_path: string;
+export interface VoiceMessageData extends SendOptions {
+  voice: MediaSource; // Legacy name: voice_path
   caption?: string;
   duration?: number;
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
 }
 
-export interface VideoMessageData {
-  video_path: string;
+export interface VideoMessageData extends SendOptions {
+  video: MediaSource; // Legacy name: video_path
   caption?: string;
   duration?: number;
   width?: number;
@@ -392,64 +519,410 @@ export interface VideoMessageData {
   supports_streaming?: boolean;
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
 }
 
-export interface VideoNoteMessageData {
-  video_note_path: string;
+export interface VideoNoteMessageData extends SendOptions {
+  video_note: MediaSource; // Legacy name: video_note_path
   duration?: number;
   length?: number;
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
 }
 
-export interface StickerMessageData {
-  sticker_path: string;
+export interface StickerMessageData extends SendOptions {
+  sticker: MediaSource; // Legacy name: sticker_path
   emoji?: string;
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
 }
 
-export interface AnimationMessageData {
-  animation_path: string;
+export interface AnimationMessageData extends SendOptions {
+  animation: MediaSource; // Legacy name: animation_path
   caption?: string;
   duration?: number;
   width?: number;
   height?: number;
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
 }
 
-export interface DocumentMessageData {
-  document_path: string;
+export interface DocumentMessageData extends SendOptions {
+  document: MediaSource; // Legacy name: document_path
   filename?: string;
   caption?: string;
   buttons?: ButtonInfo[][];
   reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
+}
+
+/**
+ * Where an outgoing file comes from. A plain string is a path on the
+ * ratatoskr host.
+ */
+export type MediaSource =
+  | string
+  | { type: "path"; path: string }
+  | { type: "url"; url: string } // Telegram downloads the file itself
+  | { type: "file_id"; file_id: string } // e.g. FileInfo.file_id of an incoming message
+  | { type: "base64"; data: string; filename: string };
+
+/**
+ * 2-10 items sent as one album. Documents and audio files can only be grouped
+ * with items of the same type.
+ */
+export interface MediaGroupData extends SendOptions {
+  media: MediaGroupItem[];
+}
+
+export interface MediaGroupItem {
+  type: "photo" | "video" | "document" | "audio";
+  media: MediaSource; // Legacy name: path
+  caption?: string;
+  parse_mode?: string; // "HTML", "Markdown", etc.
+}
+
+export interface LocationMessageData extends SendOptions {
+  latitude: number;
+  longitude: number;
+  horizontal_accuracy?: number; // Meters, 0-1500
+  live_period?: number; // Seconds (60-86400) the location can be edited, or 2147483647 for indefinitely
+  heading?: number; // Degrees, 1-360; live locations only
+  proximity_alert_radius?: number; // Meters; live locations only
+  buttons?: ButtonInfo[][];
+  reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
+}
+
+export interface VenueMessageData extends SendOptions {
+  latitude: number;
+  longitude: number;
+  title: string;
+  address: string;
+  foursquare_id?: string;
+  foursquare_type?: string;
+  google_place_id?: string;
+  google_place_type?: string;
+  buttons?: ButtonInfo[][];
+  reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
+}
+
+export interface ContactMessageData extends SendOptions {
+  phone_number: string;
+  first_name: string;
+  last_name?: string;
+  vcard?: string;
+  buttons?: ButtonInfo[][];
+  reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
+}
+
+export interface DiceMessageData extends SendOptions {
+  emoji?: "🎲" | "🎯" | "🏀" | "⚽" | "🎳" | "🎰"; // Defaults to 🎲
+  buttons?: ButtonInfo[][];
+  reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
+}
+
+export interface PollMessageData extends SendOptions {
+  question: string;
+  options: string[];
+  type?: "regular" | "quiz"; // Defaults to "regular"
+  is_anonymous?: boolean; // Defaults to true; answers are only published for non-anonymous polls
+  allows_multiple_answers?: boolean;
+  correct_option_id?: number; // 0-based; required for quizzes
+  explanation?: string; // Shown for wrong quiz answers
+  explanation_parse_mode?: string; // "HTML", "Markdown", etc.
+  open_period?: number; // Seconds, 5-600
+  buttons?: ButtonInfo[][];
+  reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup; // Instead of buttons or reply_keyboard
+}
+
+export interface StopPollData {
+  message_id: number; // The message containing the poll
+  buttons?: ButtonInfo[][];
+}
+
+/**
+ * One event of a live-edited message. `start` sends the message, `append` adds
+ * text (edits are coalesced), `finish` does the final edit. Text beyond 4096
+ * characters continues in a new message.
+ */
+export interface StreamMessageData extends SendOptions {
+  stream_id: string; // Unique among the chat's open streams
+  action: "start" | "append" | "finish";
+  text?: string; // Initial text for start, the chunk to add otherwise
+  parse_mode?: string; // "HTML", "Markdown", etc.; read on start
+  buttons?: ButtonInfo[][]; // Attached to the last message on finish
 }
 
 export interface EditMessageData {
   message_id: number;
   new_text?: string;
   new_buttons?: ButtonInfo[][];
+  parse_mode?: string; // "HTML", "Markdown", etc.; applies to new_text
+}
+
+export interface EditCaptionData {
+  message_id: number;
+  caption?: string; // Omit to remove the caption
+  parse_mode?: string; // "HTML", "Markdown", etc.
+  buttons?: ButtonInfo[][];
+}
+
+export interface EditMediaData {
+  message_id: number;
+  media: MediaGroupItem; // The new photo, video, document or audio file
+  buttons?: ButtonInfo[][];
+}
+
+export interface EditLiveLocationData {
+  message_id: number;
+  latitude: number;
+  longitude: number;
+  horizontal_accuracy?: number; // Meters, 0-1500
+  heading?: number; // Degrees, 1-360
+  proximity_alert_radius?: number; // Meters
+  buttons?: ButtonInfo[][];
 }
 
 export interface DeleteMessageData {
   message_id: number;
 }
 
+export interface ForwardMessageData {
+  from_chat_id: number;
+  message_id: number;
+  disable_notification?: boolean;
+  protect_content?: boolean;
+}
+
+export interface CopyMessageData extends Omit<SendOptions, "message_effect_id"> {
+  from_chat_id: number;
+  message_id: number;
+  caption?: string; // Replaces the caption of a media message
+  parse_mode?: string; // "HTML", "Markdown", etc.
+  buttons?: ButtonInfo[][];
+  reply_keyboard?: ReplyKeyboardMarkup;
+  reply_markup?: ReplyMarkup;
+}
+
+export interface CopyMessagesData {
+  from_chat_id: number;
+  message_ids: number[]; // 1-100, in increasing order
+  remove_caption?: boolean;
+  disable_notification?: boolean;
+  protect_content?: boolean;
+}
+
+export interface PinChatMessageData {
+  message_id: number;
+  disable_notification?: boolean;
+}
+
+export interface UnpinChatMessageData {
+  message_id?: number; // The most recently pinned message if omitted
+}
+
+export interface SetReactionData {
+  message_id: number;
+  reaction: Reaction[]; // Replaces the bot's reactions; [] removes them. "paid" is not allowed
+  is_big?: boolean;
+}
+
+export interface BanChatMemberData {
+  user_id: number;
+  until_date?: string; // ISO 8601; permanent if omitted
+  revoke_messages?: boolean; // Delete all of the user's messages in the chat
+}
+
+export interface UnbanChatMemberData {
+  user_id: number;
+  only_if_banned?: boolean; // Otherwise a member is removed from the chat, too
+}
+
+export interface RestrictChatMemberData {
+  user_id: number;
+  permissions: ChatPermissions;
+  until_date?: string; // ISO 8601; permanent if omitted
+  use_independent_chat_permissions?: boolean;
+}
+
+/**
+ * What a restricted member may do; omitted permissions are denied
+ */
+export interface ChatPermissions {
+  can_send_messages?: boolean;
+  can_send_audios?: boolean;
+  can_send_documents?: boolean;
+  can_send_photos?: boolean;
+  can_send_videos?: boolean;
+  can_send_video_notes?: boolean;
+  can_send_voice_notes?: boolean;
+  can_send_polls?: boolean;
+  can_send_other_messages?: boolean;
+  can_add_web_page_previews?: boolean;
+  can_change_info?: boolean;
+  can_invite_users?: boolean;
+  can_pin_messages?: boolean;
+  can_manage_topics?: boolean;
+}
+
+/**
+ * Admin rights to grant; omitted rights are revoked
+ */
+export interface PromoteChatMemberData {
+  user_id: number;
+  is_anonymous?: boolean;
+  can_manage_chat?: boolean;
+  can_delete_messages?: boolean;
+  can_manage_video_chats?: boolean;
+  can_restrict_members?: boolean;
+  can_promote_members?: boolean;
+  can_change_info?: boolean;
+  can_invite_users?: boolean;
+  can_post_messages?: boolean; // Channels only
+  can_edit_messages?: boolean; // Channels only
+  can_pin_messages?: boolean;
+  can_post_stories?: boolean;
+  can_edit_stories?: boolean;
+  can_delete_stories?: boolean;
+  can_manage_topics?: boolean;
+}
+
+export interface ChatJoinRequestDecisionData {
+  user_id: number;
+}
+
+export interface AnswerCallbackQueryData {
+  callback_query_id: string; // From the incoming CallbackQuery
+  text?: string; // Notification text, 0-200 characters
+  show_alert?: boolean; // Show an alert instead of a notification
+  url?: string;
+  cache_time?: number; // Seconds the answer may be cached client-side
+}
+
 export interface TypingMessageData {
-  action?: "typing"; // For extensibility, could support other actions like "upload_photo"
+  action?: ChatAction; // Defaults to "typing"
+  until_reply?: boolean; // Repeat every ~4s until the next message for the chat is sent
+  timeout_secs?: number; // Stop repeating after this long; defaults to 60
+}
+
+export type ChatAction =
+  | "typing"
+  | "upload_photo"
+  | "record_video"
+  | "upload_video"
+  | "record_voice"
+  | "upload_voice"
+  | "upload_document"
+  | "choose_sticker"
+  | "find_location"
+  | "record_video_note"
+  | "upload_video_note";
+
+// =============================================================================
+// DELIVERY RECEIPT TYPES (Kafka RECEIPTS topic)
+// =============================================================================
+
+/**
+ * Published after every outgoing message that could be parsed
+ */
+export interface DeliveryReceipt {
+  trace_id: string; // UUID of the outgoing message
+  chat_id: number;
+  message_ids: number[]; // Telegram messages that were sent, edited or deleted; for a failure, the parts sent before it
+  status: "delivered" | "failed";
+  error: string | null; // Set when status is "failed"
+  poll_id: string | null; // Set for a delivered PollMessage; matches Poll/PollAnswer poll_id
+  timestamp: string; // ISO 8601 datetime string
+}
+
+// =============================================================================
+// METRICS TYPES (Kafka METRICS topic)
+// =============================================================================
+
+/**
+ * Published every `[rate_limit] metrics_interval_secs`
+ */
+export interface RateLimitMetrics {
+  queue_depth: number; // Outgoing messages waiting for the rate limiter
+  timestamp: string; // ISO 8601 datetime string
+}
+
+// =============================================================================
+// DEAD-LETTER TYPES (Kafka DLQ topic)
+// =============================================================================
+
+/**
+ * An outgoing message that could not be delivered
+ */
+export interface DeadLetterRecord {
+  trace_id: string | null; // UUID of the original message, if it could be read
+  chat_id: number | null; // Target chat of the original message, if it could be read
+  error_kind: DeadLetterErrorKind;
+  error_message: string;
+  attempts: number;
+  sent_message_ids: number[]; // Parts of a split message sent before the failure
+  original_payload: string; // The payload exactly as received
+  timestamp: string; // ISO 8601 datetime string
 }
 
+export type DeadLetterErrorKind =
+  | "malformed_payload"
+  | "invalid_message"
+  | "telegram_api"
+  | "rate_limited"
+  | "chat_migrated"
+  | "network"
+  | "invalid_response"
+  | "io"
+  | "panicked";
+
 // =============================================================================
 // KEYBOARD AND BUTTON TYPES
 // =============================================================================
 
-export interface ButtonInfo {
-  text: string;
-  callback_data: string;
+/**
+ * An inline button: `text` plus exactly one action field
+ */
+export type ButtonInfo = { text: string } & (
+  | { callback_data: string }
+  | { url: string }
+  | { web_app: WebApp } // Private chats only
+  | { login_url: LoginUrl }
+  | { switch_inline_query: string }
+  | { switch_inline_query_current_chat: string }
+  | { copy_text: CopyText }
+  | { pay: true } // First button of an invoice only
+);
+
+export interface LoginUrl {
+  url: string;
+  forward_text?: string;
+  bot_username?: string;
+  request_write_access?: boolean;
 }
 
+/**
+ * Any markup for a sent message. `buttons` and `reply_keyboard` are shorthands
+ * for "inline" and "reply"; a message may set only one of the three.
+ */
+export type ReplyMarkup =
+  | { type: "inline"; buttons: ButtonInfo[][] }
+  | ({ type: "reply" } & ReplyKeyboardMarkup)
+  | { type: "remove"; selective?: boolean } // Hide a custom keyboard
+  | {
+      type: "force_reply"; // Open a reply to the message
+      input_field_placeholder?: string;
+      selective?: boolean;
+    };
+
 export interface ReplyKeyboardButton {
   text: string;
   request_contact?: boolean;
@@ -466,6 +939,10 @@ export interface WebApp {
   url: string;
 }
 
+export interface CopyText {
+  text: string;
+}
+
 export interface ReplyKeyboardMarkup {
   keyboard: ReplyKeyboardButton[][];
   is_persistent?: boolean;
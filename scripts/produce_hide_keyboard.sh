@@ -24,12 +24,8 @@ cat > "$TMP_FILE" << EOF
     "type": "TextMessage",
     "data": {
       "text": "$MESSAGE_TEXT",
-      "buttons": null,
-      "reply_keyboard": {
-        "keyboard": [],
-        "resize_keyboard": true,
-        "one_time_keyboard": false,
-        "input_field_placeholder": null
+      "reply_markup": {
+        "type": "remove"
       },
       "parse_mode": null,
       "disable_web_page_preview": false
@@ -1,26 +1,57 @@
-use crate::broker::{BoxStream, MessageBroker};
+use crate::broker::{Acknowledger, BoxStream, Delivery, MessageBroker};
 use anyhow::{Context, Result};
 use async_trait::async_trait;
 use rdkafka::admin::{AdminClient, AdminOptions, NewTopic, TopicReplication};
 use rdkafka::client::DefaultClientContext;
 use rdkafka::config::ClientConfig;
-use rdkafka::consumer::{Consumer, StreamConsumer};
+use rdkafka::consumer::{CommitMode, Consumer, StreamConsumer};
 use rdkafka::message::OwnedHeaders;
 use rdkafka::producer::{FutureProducer, FutureRecord};
-use rdkafka::Message;
+use rdkafka::{Message, Offset, TopicPartitionList};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
 use std::time::Duration;
 use tokio::sync::mpsc;
-use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Options for the Kafka broker (`[kafka]` section of the config file).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct KafkaOptions {
+    /// Comma-separated list of Kafka brokers
+    pub brokers: String,
+    /// Prefix for topic names
+    pub topic_prefix: String,
+    /// Consumer group ID
+    pub group_id: String,
+}
+
+impl Default for KafkaOptions {
+    fn default() -> Self {
+        Self {
+            brokers: "localhost:9092".to_string(),
+            topic_prefix: "ratatoskr".to_string(),
+            group_id: "ratatoskr".to_string(),
+        }
+    }
+}
 
 /// KafkaBroker implements MessageBroker using Apache Kafka.
 /// - Publishes incoming Telegram messages to `{prefix}.in` topic
 /// - Consumes outgoing messages from `{prefix}.out` topic
+/// - Publishes undeliverable outgoing messages to `{prefix}.dlq` topic
+/// - Publishes delivery receipts to `{prefix}.receipts` topic
+/// - Publishes metrics records to `{prefix}.metrics` topic
 pub struct KafkaBroker {
     producer: FutureProducer,
     brokers: String,
     topic_in: String,
     topic_out: String,
+    topic_dlq: String,
+    topic_receipts: String,
+    topic_metrics: String,
     group_id: String,
 }
 
@@ -47,10 +78,22 @@ impl KafkaBroker {
             brokers: brokers.to_string(),
             topic_in: format!("{}.in", prefix),
             topic_out: format!("{}.out", prefix),
+            topic_dlq: format!("{}.dlq", prefix),
+            topic_receipts: format!("{}.receipts", prefix),
+            topic_metrics: format!("{}.metrics", prefix),
             group_id: group.to_string(),
         })
     }
 
+    /// Create a new KafkaBroker from config file options.
+    pub fn from_options(options: &KafkaOptions) -> Result<Self> {
+        Self::new(
+            &options.brokers,
+            Some(&options.topic_prefix),
+            Some(&options.group_id),
+        )
+    }
+
     /// Ensure topics exist, creating them if necessary.
     pub async fn ensure_topics(&self) -> Result<()> {
         let admin: AdminClient<DefaultClientContext> = ClientConfig::new()
@@ -61,6 +104,9 @@ impl KafkaBroker {
         let topics = [
             NewTopic::new(&self.topic_in, 1, TopicReplication::Fixed(1)),
             NewTopic::new(&self.topic_out, 1, TopicReplication::Fixed(1)),
+            NewTopic::new(&self.topic_dlq, 1, TopicReplication::Fixed(1)),
+            NewTopic::new(&self.topic_receipts, 1, TopicReplication::Fixed(1)),
+            NewTopic::new(&self.topic_metrics, 1, TopicReplication::Fixed(1)),
         ];
 
         let opts = AdminOptions::new().operation_timeout(Some(Duration::from_secs(5)));
@@ -85,12 +131,9 @@ impl KafkaBroker {
 
         Ok(())
     }
-}
 
-#[async_trait]
-impl MessageBroker for KafkaBroker {
-    async fn publish(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
-        let mut record = FutureRecord::to(&self.topic_in)
+    async fn send(&self, topic: &str, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        let mut record = FutureRecord::to(topic)
             .payload(payload)
             .headers(OwnedHeaders::new());
 
@@ -104,7 +147,7 @@ impl MessageBroker for KafkaBroker {
             .map_err(|(err, _)| anyhow::anyhow!("Failed to send message to Kafka: {}", err))?;
 
         tracing::debug!(
-            topic = %self.topic_in,
+            topic = %topic,
             key = ?key,
             payload_size = payload.len(),
             "Published message to Kafka"
@@ -112,13 +155,33 @@ impl MessageBroker for KafkaBroker {
 
         Ok(())
     }
+}
 
-    async fn subscribe<'a>(&'a self) -> Result<BoxStream<'a, Vec<u8>>> {
+#[async_trait]
+impl MessageBroker for KafkaBroker {
+    async fn publish(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        self.send(&self.topic_in, key, payload).await
+    }
+
+    async fn publish_dead_letter(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        self.send(&self.topic_dlq, key, payload).await
+    }
+
+    async fn publish_receipt(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        self.send(&self.topic_receipts, key, payload).await
+    }
+
+    async fn publish_metrics(&self, payload: &[u8]) -> Result<()> {
+        self.send(&self.topic_metrics, None, payload).await
+    }
+
+    async fn subscribe<'a>(&'a self) -> Result<BoxStream<'a, Delivery>> {
+        // Offsets are committed by the acknowledger once a message was handled,
+        // so a crash mid-send leads to redelivery instead of a lost reply.
         let consumer: StreamConsumer = ClientConfig::new()
             .set("bootstrap.servers", &self.brokers)
             .set("group.id", &self.group_id)
-            .set("enable.auto.commit", "true")
-            .set("auto.commit.interval.ms", "1000")
+            .set("enable.auto.commit", "false")
             .set("auto.offset.reset", "latest")
             .set("session.timeout.ms", "6000")
             .create()
@@ -134,6 +197,8 @@ impl MessageBroker for KafkaBroker {
             "Subscribed to Kafka topic"
         );
 
+        let consumer = Arc::new(consumer);
+        let tracker = Arc::new(Mutex::new(OffsetTracker::default()));
         let (tx, rx) = mpsc::channel(32);
 
         tokio::spawn(async move {
@@ -141,19 +206,34 @@ impl MessageBroker for KafkaBroker {
             while let Some(result) = stream.next().await {
                 match result {
                     Ok(msg) => {
-                        if let Some(payload) = msg.payload() {
-                            tracing::debug!(
-                                topic = %msg.topic(),
-                                partition = msg.partition(),
-                                offset = msg.offset(),
-                                key = ?msg.key().map(|k| String::from_utf8_lossy(k)),
-                                payload_size = payload.len(),
-                                "Received message from Kafka"
-                            );
-                            if tx.send(payload.to_vec()).await.is_err() {
-                                tracing::warn!("Kafka consumer channel closed");
-                                break;
+                        let acknowledger = KafkaAcknowledger {
+                            consumer: consumer.clone(),
+                            tracker: tracker.clone(),
+                            topic: msg.topic().to_string(),
+                            partition: msg.partition(),
+                            offset: msg.offset(),
+                        };
+                        tracker.lock().unwrap().track(msg.partition(), msg.offset());
+
+                        let Some(payload) = msg.payload() else {
+                            // Nothing to deliver, but the offset still has to move on.
+                            if let Err(e) = Box::new(acknowledger).ack().await {
+                                tracing::warn!(error = %e, "Failed to commit empty Kafka message");
                             }
+                            continue;
+                        };
+                        tracing::debug!(
+                            topic = %msg.topic(),
+                            partition = msg.partition(),
+                            offset = msg.offset(),
+                            key = ?msg.key().map(|k| String::from_utf8_lossy(k)),
+                            payload_size = payload.len(),
+                            "Received message from Kafka"
+                        );
+                        let delivery = Delivery::with_acknowledger(payload.to_vec(), acknowledger);
+                        if tx.send(delivery).await.is_err() {
+                            tracing::warn!("Kafka consumer channel closed");
+                            break;
                         }
                     }
                     Err(e) => {
@@ -168,3 +248,143 @@ impl MessageBroker for KafkaBroker {
         Ok(Box::pin(stream))
     }
 }
+
+/// Commits the offset of a single consumed message once it is acked.
+struct KafkaAcknowledger {
+    consumer: Arc<StreamConsumer>,
+    tracker: Arc<Mutex<OffsetTracker>>,
+    topic: String,
+    partition: i32,
+    offset: i64,
+}
+
+#[async_trait]
+impl Acknowledger for KafkaAcknowledger {
+    async fn ack(self: Box<Self>) -> Result<()> {
+        let commit = self
+            .tracker
+            .lock()
+            .unwrap()
+            .ack(self.partition, self.offset);
+        let Some(next_offset) = commit else {
+            tracing::debug!(
+                partition = self.partition,
+                offset = self.offset,
+                "Acked Kafka message, waiting for earlier offsets before committing"
+            );
+            return Ok(());
+        };
+
+        let mut tpl = TopicPartitionList::new();
+        tpl.add_partition_offset(&self.topic, self.partition, Offset::Offset(next_offset))
+            .context("Failed to build Kafka commit")?;
+        self.consumer
+            .commit(&tpl, CommitMode::Async)
+            .context("Failed to commit Kafka offset")?;
+
+        tracing::debug!(
+            topic = %self.topic,
+            partition = self.partition,
+            offset = next_offset,
+            "Committed Kafka offset"
+        );
+        Ok(())
+    }
+
+    async fn nack(self: Box<Self>) -> Result<()> {
+        // Redelivering it now would put it behind later messages for the same
+        // chat, so the offset stays in flight until the consumer restarts.
+        tracing::error!(
+            topic = %self.topic,
+            partition = self.partition,
+            offset = self.offset,
+            "Kafka message not acknowledged, offsets from here on stay uncommitted until restart"
+        );
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OffsetState {
+    InFlight,
+    Acked,
+}
+
+/// Tracks in-flight offsets per partition.
+///
+/// Acks can arrive out of order, so a commit only ever covers the contiguous
+/// run of acked offsets at the front of a partition. A nacked offset stays in
+/// flight and holds back later commits, which makes Kafka redeliver it (and
+/// everything after it) when the consumer group restarts.
+#[derive(Debug, Default)]
+struct OffsetTracker {
+    partitions: HashMap<i32, BTreeMap<i64, OffsetState>>,
+}
+
+impl OffsetTracker {
+    fn track(&mut self, partition: i32, offset: i64) {
+        self.partitions
+            .entry(partition)
+            .or_default()
+            .insert(offset, OffsetState::InFlight);
+    }
+
+    /// Mark `offset` as handled; returns the offset to commit, if the
+    /// committable prefix advanced.
+    fn ack(&mut self, partition: i32, offset: i64) -> Option<i64> {
+        let offsets = self.partitions.get_mut(&partition)?;
+        offsets.insert(offset, OffsetState::Acked);
+
+        let mut committed = None;
+        while let Some(entry) = offsets.first_entry() {
+            if *entry.get() != OffsetState::Acked {
+                break;
+            }
+            committed = Some(*entry.key());
+            entry.remove();
+        }
+        committed.map(|offset| offset + 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ack_in_order_commits_next_offset() {
+        let mut tracker = OffsetTracker::default();
+        tracker.track(0, 10);
+        tracker.track(0, 11);
+
+        assert_eq!(tracker.ack(0, 10), Some(11));
+        assert_eq!(tracker.ack(0, 11), Some(12));
+    }
+
+    #[test]
+    fn out_of_order_ack_waits_for_earlier_offsets() {
+        let mut tracker = OffsetTracker::default();
+        tracker.track(0, 10);
+        tracker.track(0, 11);
+        tracker.track(0, 12);
+
+        assert_eq!(tracker.ack(0, 12), None);
+        assert_eq!(tracker.ack(0, 11), None);
+        assert_eq!(tracker.ack(0, 10), Some(13));
+    }
+
+    #[test]
+    fn nacked_offset_holds_back_commits() {
+        let mut tracker = OffsetTracker::default();
+        tracker.track(0, 10);
+        tracker.track(0, 11);
+        tracker.track(1, 5);
+
+        // Offset 10 was nacked and stays in flight.
+        assert_eq!(tracker.ack(0, 11), None);
+        // Other partitions are unaffected.
+        assert_eq!(tracker.ack(1, 5), Some(6));
+        assert_eq!(tracker.ack(0, 10), Some(12));
+        assert!(tracker.partitions[&0].is_empty());
+    }
+}
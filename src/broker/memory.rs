@@ -0,0 +1,253 @@
+use crate::broker::{Acknowledger, BoxStream, Delivery, MessageBroker};
+use anyhow::{Result, bail};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::StreamExt;
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Options for the in-memory broker (`[memory]` section of the config file).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct MemoryOptions {
+    /// Capacity of the incoming and outgoing channels
+    pub capacity: usize,
+}
+
+impl Default for MemoryOptions {
+    fn default() -> Self {
+        Self { capacity: 256 }
+    }
+}
+
+/// A message published through the broker, as seen by an `incoming()` receiver.
+#[derive(Debug, Clone)]
+pub struct PublishedMessage {
+    pub key: Option<String>,
+    pub payload: Vec<u8>,
+}
+
+/// How the consumer settled an outgoing message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Settlement {
+    Acked(Vec<u8>),
+    Nacked(Vec<u8>),
+}
+
+/// InMemoryBroker implements MessageBroker with in-process tokio channels.
+/// - Published incoming messages are broadcast to every `incoming()` receiver
+///   (and dropped when nobody is listening)
+/// - Outgoing messages injected with `send_outgoing()` are delivered to the
+///   single `subscribe()` stream
+/// - Acks and nacks are recorded and can be inspected with `settlements()`
+/// - Dead letters, receipts and metrics are kept and can be inspected with
+///   `dead_letters()`, `receipts()` and `metrics()`
+pub struct InMemoryBroker {
+    incoming_tx: broadcast::Sender<PublishedMessage>,
+    dead_letters: Mutex<Vec<PublishedMessage>>,
+    receipts: Mutex<Vec<PublishedMessage>>,
+    metrics: Mutex<Vec<PublishedMessage>>,
+    outgoing_tx: mpsc::Sender<Vec<u8>>,
+    outgoing_rx: Mutex<Option<mpsc::Receiver<Vec<u8>>>>,
+    settlements: Arc<Mutex<Vec<Settlement>>>,
+}
+
+impl InMemoryBroker {
+    /// Create a new InMemoryBroker whose channels hold up to `capacity` messages.
+    pub fn new(capacity: usize) -> Self {
+        let (incoming_tx, _) = broadcast::channel(capacity);
+        let (outgoing_tx, outgoing_rx) = mpsc::channel(capacity);
+        Self {
+            incoming_tx,
+            dead_letters: Mutex::default(),
+            receipts: Mutex::default(),
+            metrics: Mutex::default(),
+            outgoing_tx,
+            outgoing_rx: Mutex::new(Some(outgoing_rx)),
+            settlements: Arc::default(),
+        }
+    }
+
+    /// Create a new InMemoryBroker from config file options.
+    pub fn from_options(options: &MemoryOptions) -> Self {
+        Self::new(options.capacity)
+    }
+
+    /// Receive every message published after this call.
+    pub fn incoming(&self) -> broadcast::Receiver<PublishedMessage> {
+        self.incoming_tx.subscribe()
+    }
+
+    /// Queue an outgoing message payload for the `subscribe()` stream.
+    pub async fn send_outgoing(&self, payload: impl Into<Vec<u8>>) -> Result<()> {
+        self.outgoing_tx
+            .send(payload.into())
+            .await
+            .map_err(|_| anyhow::anyhow!("In-memory outgoing channel closed"))
+    }
+
+    /// Dead letters published so far, oldest first.
+    pub fn dead_letters(&self) -> Vec<PublishedMessage> {
+        self.dead_letters.lock().unwrap().clone()
+    }
+
+    /// Delivery receipts published so far, oldest first.
+    pub fn receipts(&self) -> Vec<PublishedMessage> {
+        self.receipts.lock().unwrap().clone()
+    }
+
+    /// Metrics records published so far, oldest first.
+    pub fn metrics(&self) -> Vec<PublishedMessage> {
+        self.metrics.lock().unwrap().clone()
+    }
+
+    /// Outgoing messages settled so far, in settlement order.
+    pub fn settlements(&self) -> Vec<Settlement> {
+        self.settlements.lock().unwrap().clone()
+    }
+}
+
+/// Records the settlement of an in-memory delivery.
+struct MemoryAcknowledger {
+    payload: Vec<u8>,
+    settlements: Arc<Mutex<Vec<Settlement>>>,
+}
+
+#[async_trait]
+impl Acknowledger for MemoryAcknowledger {
+    async fn ack(self: Box<Self>) -> Result<()> {
+        self.settlements
+            .lock()
+            .unwrap()
+            .push(Settlement::Acked(self.payload));
+        Ok(())
+    }
+
+    async fn nack(self: Box<Self>) -> Result<()> {
+        self.settlements
+            .lock()
+            .unwrap()
+            .push(Settlement::Nacked(self.payload));
+        Ok(())
+    }
+}
+
+impl Default for InMemoryBroker {
+    fn default() -> Self {
+        Self::from_options(&MemoryOptions::default())
+    }
+}
+
+#[async_trait]
+impl MessageBroker for InMemoryBroker {
+    async fn publish(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        let message = PublishedMessage {
+            key: key.map(String::from),
+            payload: payload.to_vec(),
+        };
+        match self.incoming_tx.send(message) {
+            Ok(receivers) => {
+                tracing::debug!(key = ?key, payload_size = payload.len(), receivers, "Published message in memory");
+            }
+            Err(_) => {
+                tracing::debug!(key = ?key, payload_size = payload.len(), "No in-memory receivers, dropping message");
+            }
+        }
+        Ok(())
+    }
+
+    async fn publish_dead_letter(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        self.dead_letters.lock().unwrap().push(PublishedMessage {
+            key: key.map(String::from),
+            payload: payload.to_vec(),
+        });
+        tracing::debug!(key = ?key, payload_size = payload.len(), "Stored dead letter in memory");
+        Ok(())
+    }
+
+    async fn publish_receipt(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        self.receipts.lock().unwrap().push(PublishedMessage {
+            key: key.map(String::from),
+            payload: payload.to_vec(),
+        });
+        tracing::debug!(key = ?key, payload_size = payload.len(), "Stored receipt in memory");
+        Ok(())
+    }
+
+    async fn publish_metrics(&self, payload: &[u8]) -> Result<()> {
+        self.metrics.lock().unwrap().push(PublishedMessage {
+            key: None,
+            payload: payload.to_vec(),
+        });
+        tracing::debug!(payload_size = payload.len(), "Stored metrics in memory");
+        Ok(())
+    }
+
+    async fn subscribe<'a>(&'a self) -> Result<BoxStream<'a, Delivery>> {
+        let Some(rx) = self.outgoing_rx.lock().unwrap().take() else {
+            bail!("InMemoryBroker supports a single subscriber");
+        };
+        let settlements = self.settlements.clone();
+        let stream = ReceiverStream::new(rx).map(move |payload| {
+            let acknowledger = MemoryAcknowledger {
+                payload: payload.clone(),
+                settlements: settlements.clone(),
+            };
+            Delivery::with_acknowledger(payload, acknowledger)
+        });
+        Ok(Box::pin(stream))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn publish_reaches_incoming_receivers() {
+        let broker = InMemoryBroker::default();
+        let mut rx = broker.incoming();
+
+        broker.publish(Some("42"), b"hello").await.unwrap();
+
+        let message = rx.recv().await.unwrap();
+        assert_eq!(message.key.as_deref(), Some("42"));
+        assert_eq!(message.payload, b"hello");
+    }
+
+    #[tokio::test]
+    async fn publish_without_receivers_succeeds() {
+        let broker = InMemoryBroker::default();
+        broker.publish(None, b"dropped").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn subscribe_yields_outgoing_once() {
+        let broker = InMemoryBroker::default();
+        broker.send_outgoing("out").await.unwrap();
+
+        let mut stream = broker.subscribe().await.unwrap();
+        assert_eq!(stream.next().await.unwrap().payload, b"out");
+        assert!(broker.subscribe().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn settlements_are_recorded() {
+        let broker = InMemoryBroker::default();
+        broker.send_outgoing("first").await.unwrap();
+        broker.send_outgoing("second").await.unwrap();
+
+        let mut stream = broker.subscribe().await.unwrap();
+        stream.next().await.unwrap().ack().await.unwrap();
+        stream.next().await.unwrap().nack().await.unwrap();
+
+        assert_eq!(
+            broker.settlements(),
+            vec![
+                Settlement::Acked(b"first".to_vec()),
+                Settlement::Nacked(b"second".to_vec()),
+            ]
+        );
+    }
+}
@@ -1,5 +1,8 @@
+use crate::config::ServeConfig;
 use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
 use std::pin::Pin;
+use std::sync::Arc;
 use tokio_stream::Stream;
 
 pub type BoxStream<'a, T> = Pin<Box<dyn Stream<Item = T> + Send + 'a>>;
@@ -9,7 +12,125 @@ pub trait MessageBroker: Send + Sync {
     /// Publish a message with an optional key for partitioning.
     /// The key is typically the telegram_user_id for incoming messages.
     async fn publish(&self, key: Option<&str>, payload: &[u8]) -> anyhow::Result<()>;
-    async fn subscribe<'a>(&'a self) -> anyhow::Result<BoxStream<'a, Vec<u8>>>;
+    /// Publish an outgoing message that could not be delivered, for auditing
+    /// and replay. The key is typically the target chat_id.
+    async fn publish_dead_letter(&self, key: Option<&str>, payload: &[u8]) -> anyhow::Result<()>;
+    /// Publish a delivery receipt for an outgoing message. The key is
+    /// typically the target chat_id.
+    async fn publish_receipt(&self, key: Option<&str>, payload: &[u8]) -> anyhow::Result<()>;
+    /// Publish a periodic metrics record, such as the rate limiter's queue depth.
+    async fn publish_metrics(&self, payload: &[u8]) -> anyhow::Result<()>;
+    /// Stream outgoing messages. Each delivery must be acked once it has been
+    /// handled, or nacked to leave it for redelivery.
+    async fn subscribe<'a>(&'a self) -> anyhow::Result<BoxStream<'a, Delivery>>;
+}
+
+/// Settles a delivery with the broker it came from.
+#[async_trait]
+pub trait Acknowledger: Send {
+    /// The message was handled and must not be delivered again.
+    async fn ack(self: Box<Self>) -> anyhow::Result<()>;
+    /// The message was not handled and should be delivered again.
+    async fn nack(self: Box<Self>) -> anyhow::Result<()>;
+}
+
+/// An outgoing message received from a broker.
+pub struct Delivery {
+    pub payload: Vec<u8>,
+    acknowledger: Option<Box<dyn Acknowledger>>,
+}
+
+impl Delivery {
+    /// A delivery that needs no acknowledgement, for transports without redelivery.
+    pub fn new(payload: Vec<u8>) -> Self {
+        Self {
+            payload,
+            acknowledger: None,
+        }
+    }
+
+    /// A delivery settled through `acknowledger`.
+    pub fn with_acknowledger(payload: Vec<u8>, acknowledger: impl Acknowledger + 'static) -> Self {
+        Self {
+            payload,
+            acknowledger: Some(Box::new(acknowledger)),
+        }
+    }
+
+    /// Acknowledge the message as handled.
+    pub async fn ack(self) -> anyhow::Result<()> {
+        match self.acknowledger {
+            Some(acknowledger) => acknowledger.ack().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Reject the message so that the broker delivers it again.
+    pub async fn nack(self) -> anyhow::Result<()> {
+        match self.acknowledger {
+            Some(acknowledger) => acknowledger.nack().await,
+            None => Ok(()),
+        }
+    }
+}
+
+impl std::fmt::Debug for Delivery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Delivery")
+            .field("payload_size", &self.payload.len())
+            .field("acknowledged", &self.acknowledger.is_some())
+            .finish()
+    }
 }
 
 pub mod kafka;
+pub mod memory;
+pub mod pipe;
+
+use kafka::KafkaBroker;
+use memory::InMemoryBroker;
+use pipe::PipeBroker;
+
+/// Transport used to exchange messages with the backend.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum BrokerKind {
+    /// Kafka topics `{prefix}.in` / `{prefix}.out` / `{prefix}.dlq` / `{prefix}.receipts` / `{prefix}.metrics`
+    #[default]
+    Kafka,
+    /// JSON lines on stdout / a named pipe
+    Pipe,
+    /// In-process channels, for tests and embedding
+    Memory,
+}
+
+/// Build the broker selected in `config`, creating any backend resources it needs.
+pub async fn connect(config: &ServeConfig) -> anyhow::Result<Arc<dyn MessageBroker>> {
+    match config.broker_kind() {
+        BrokerKind::Kafka => {
+            tracing::info!(
+                brokers = %config.kafka.brokers,
+                topic_prefix = %config.kafka.topic_prefix,
+                group_id = %config.kafka.group_id,
+                "Using Kafka broker"
+            );
+            let kafka_broker = KafkaBroker::from_options(&config.kafka)?;
+            kafka_broker.ensure_topics().await?;
+            Ok(Arc::new(kafka_broker))
+        }
+        BrokerKind::Pipe => {
+            tracing::info!(
+                outbound_path = %config.pipe.outbound_path.display(),
+                dead_letter_path = ?config.pipe.dead_letter_path,
+                receipts_path = ?config.pipe.receipts_path,
+                metrics_path = ?config.pipe.metrics_path,
+                "Using pipe broker"
+            );
+            Ok(Arc::new(PipeBroker::from_options(&config.pipe)))
+        }
+        BrokerKind::Memory => {
+            tracing::info!(capacity = config.memory.capacity, "Using in-memory broker");
+            Ok(Arc::new(InMemoryBroker::from_options(&config.memory)))
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use crate::broker::{BoxStream, Delivery, MessageBroker};
+use anyhow::{Context, Result, bail};
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::os::unix::fs::FileTypeExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Stdout};
+use tokio::net::unix::pipe;
+use tokio::sync::{Mutex, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
+
+/// Delay before retrying when the outbound pipe cannot be opened.
+const REOPEN_DELAY: Duration = Duration::from_secs(1);
+
+/// Options for the pipe broker (`[pipe]` section of the config file).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct PipeOptions {
+    /// Named pipe carrying `OutgoingMessage` JSON lines
+    pub outbound_path: PathBuf,
+    /// File that undeliverable outgoing messages are appended to as JSON lines;
+    /// they are only logged when unset
+    pub dead_letter_path: Option<PathBuf>,
+    /// File that delivery receipts are appended to as JSON lines; receipts
+    /// are not published when unset
+    pub receipts_path: Option<PathBuf>,
+    /// File that metrics records are appended to as JSON lines; metrics are
+    /// not published when unset
+    pub metrics_path: Option<PathBuf>,
+}
+
+impl Default for PipeOptions {
+    fn default() -> Self {
+        Self {
+            outbound_path: PathBuf::from("./ratatoskr_out.pipe"),
+            dead_letter_path: None,
+            receipts_path: None,
+            metrics_path: None,
+        }
+    }
+}
+
+/// PipeBroker implements MessageBroker over stdio and a named pipe.
+/// - Publishes incoming Telegram messages as JSON lines to stdout
+/// - Reads outgoing messages line by line from the FIFO at `outbound_path`,
+///   reopening it whenever the last writer closes its end
+/// - Appends dead letters, receipts and metrics to `dead_letter_path`,
+///   `receipts_path` and `metrics_path`, if configured
+pub struct PipeBroker {
+    outbound_path: PathBuf,
+    dead_letter_path: Option<PathBuf>,
+    receipts_path: Option<PathBuf>,
+    metrics_path: Option<PathBuf>,
+    stdout: Mutex<Stdout>,
+}
+
+impl PipeBroker {
+    /// Create a new PipeBroker.
+    ///
+    /// # Arguments
+    /// * `outbound_path` - Path to the named pipe carrying `OutgoingMessage` JSON lines
+    pub fn new(outbound_path: impl Into<PathBuf>) -> Self {
+        Self {
+            outbound_path: outbound_path.into(),
+            dead_letter_path: None,
+            receipts_path: None,
+            metrics_path: None,
+            stdout: Mutex::new(tokio::io::stdout()),
+        }
+    }
+
+    /// Create a new PipeBroker from config file options.
+    pub fn from_options(options: &PipeOptions) -> Self {
+        Self {
+            dead_letter_path: options.dead_letter_path.clone(),
+            receipts_path: options.receipts_path.clone(),
+            metrics_path: options.metrics_path.clone(),
+            ..Self::new(&options.outbound_path)
+        }
+    }
+}
+
+/// Check that `path` exists and is a named pipe rather than a regular file,
+/// which would otherwise be replayed from the start on every reopen.
+fn ensure_fifo(path: &Path) -> Result<()> {
+    let metadata = std::fs::metadata(path).with_context(|| {
+        format!(
+            "Outbound pipe not found at {}; create it with 'mkfifo {}'",
+            path.display(),
+            path.display()
+        )
+    })?;
+
+    if !metadata.file_type().is_fifo() {
+        bail!("{} is not a named pipe", path.display());
+    }
+
+    Ok(())
+}
+
+/// Append `payload` as a single line to the file at `path`, creating it if needed.
+async fn append_line(path: &Path, payload: &[u8]) -> Result<()> {
+    let mut line = payload.to_vec();
+    line.push(b'\n');
+    let mut file = tokio::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .await
+        .with_context(|| format!("Failed to open {}", path.display()))?;
+    file.write_all(&line)
+        .await
+        .with_context(|| format!("Failed to write to {}", path.display()))?;
+    file.flush()
+        .await
+        .with_context(|| format!("Failed to write to {}", path.display()))?;
+    Ok(())
+}
+
+#[async_trait]
+impl MessageBroker for PipeBroker {
+    async fn publish(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        let mut stdout = self.stdout.lock().await;
+        stdout
+            .write_all(payload)
+            .await
+            .context("Failed to write message to stdout")?;
+        stdout
+            .write_all(b"\n")
+            .await
+            .context("Failed to write message to stdout")?;
+        stdout.flush().await.context("Failed to flush stdout")?;
+
+        tracing::debug!(
+            key = ?key,
+            payload_size = payload.len(),
+            "Published message to stdout"
+        );
+
+        Ok(())
+    }
+
+    async fn publish_dead_letter(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        // Dead letters never go to stdout, where the handler would read them
+        // as incoming updates.
+        let Some(path) = &self.dead_letter_path else {
+            tracing::error!(
+                key = ?key,
+                record = %String::from_utf8_lossy(payload),
+                "Dropping dead letter, no [pipe] dead_letter_path configured"
+            );
+            return Ok(());
+        };
+
+        append_line(path, payload).await?;
+        tracing::debug!(
+            key = ?key,
+            path = %path.display(),
+            payload_size = payload.len(),
+            "Appended dead letter"
+        );
+
+        Ok(())
+    }
+
+    async fn publish_receipt(&self, key: Option<&str>, payload: &[u8]) -> Result<()> {
+        let Some(path) = &self.receipts_path else {
+            tracing::debug!(key = ?key, "Skipping receipt, no [pipe] receipts_path configured");
+            return Ok(());
+        };
+
+        append_line(path, payload).await?;
+        tracing::debug!(
+            key = ?key,
+            path = %path.display(),
+            payload_size = payload.len(),
+            "Appended receipt"
+        );
+
+        Ok(())
+    }
+
+    async fn publish_metrics(&self, payload: &[u8]) -> Result<()> {
+        let Some(path) = &self.metrics_path else {
+            tracing::debug!("Skipping metrics, no [pipe] metrics_path configured");
+            return Ok(());
+        };
+
+        append_line(path, payload).await?;
+        tracing::debug!(
+            path = %path.display(),
+            payload_size = payload.len(),
+            "Appended metrics"
+        );
+
+        Ok(())
+    }
+
+    async fn subscribe<'a>(&'a self) -> Result<BoxStream<'a, Delivery>> {
+        ensure_fifo(&self.outbound_path)?;
+
+        tracing::info!(
+            path = %self.outbound_path.display(),
+            "Reading outgoing messages from named pipe"
+        );
+
+        let (tx, rx) = mpsc::channel(32);
+        let path = self.outbound_path.clone();
+
+        tokio::spawn(async move {
+            while !tx.is_closed() {
+                // The receiver is non-blocking: reads wait on the reactor until a
+                // writer connects, and return EOF once the last writer closes.
+                let receiver = match pipe::OpenOptions::new().open_receiver(&path) {
+                    Ok(receiver) => receiver,
+                    Err(e) => {
+                        tracing::error!(path = %path.display(), error = %e, "Failed to open outbound pipe");
+                        tokio::time::sleep(REOPEN_DELAY).await;
+                        continue;
+                    }
+                };
+                let mut lines = BufReader::new(receiver).lines();
+                loop {
+                    match lines.next_line().await {
+                        Ok(Some(line)) => {
+                            let line = line.trim();
+                            if line.is_empty() {
+                                continue;
+                            }
+                            tracing::debug!(
+                                payload_size = line.len(),
+                                "Received message from named pipe"
+                            );
+                            // A pipe cannot replay lines, so deliveries need no acknowledgement.
+                            let delivery = Delivery::new(line.as_bytes().to_vec());
+                            if tx.send(delivery).await.is_err() {
+                                tracing::warn!("Pipe consumer channel closed");
+                                return;
+                            }
+                        }
+                        Ok(None) => {
+                            tracing::debug!(path = %path.display(), "Outbound pipe writer closed, reopening");
+                            break;
+                        }
+                        Err(e) => {
+                            tracing::warn!(path = %path.display(), error = %e, "Error reading from outbound pipe, reopening");
+                            break;
+                        }
+                    }
+                }
+            }
+            tracing::warn!("Pipe consumer stream ended");
+        });
+
+        Ok(Box::pin(ReceiverStream::new(rx)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::process::Command;
+    use tempfile::TempDir;
+    use tokio_stream::StreamExt;
+
+    fn make_fifo(dir: &TempDir) -> PathBuf {
+        let path = dir.path().join("out.pipe");
+        let status = Command::new("mkfifo").arg(&path).status().unwrap();
+        assert!(status.success());
+        path
+    }
+
+    fn write_to_fifo(path: PathBuf, contents: &'static str) -> std::thread::JoinHandle<()> {
+        std::thread::spawn(move || {
+            let mut writer = std::fs::OpenOptions::new().write(true).open(path).unwrap();
+            writer.write_all(contents.as_bytes()).unwrap();
+        })
+    }
+
+    #[test]
+    fn ensure_fifo_rejects_missing_path() {
+        let err = ensure_fifo(Path::new("/nonexistent/out.pipe")).unwrap_err();
+        assert!(err.to_string().contains("mkfifo"));
+    }
+
+    #[test]
+    fn ensure_fifo_rejects_regular_file() {
+        let file = tempfile::NamedTempFile::new().unwrap();
+        let err = ensure_fifo(file.path()).unwrap_err();
+        assert!(err.to_string().contains("not a named pipe"));
+    }
+
+    #[tokio::test]
+    async fn subscribe_reads_lines_and_reopens_after_writer_closes() {
+        let dir = TempDir::new().unwrap();
+        let path = make_fifo(&dir);
+        let broker = PipeBroker::new(&path);
+        let mut stream = broker.subscribe().await.unwrap();
+
+        let first_writer = write_to_fifo(path.clone(), "first\n\nsecond\n");
+        assert_eq!(stream.next().await.unwrap().payload, b"first");
+        assert_eq!(stream.next().await.unwrap().payload, b"second");
+        first_writer.join().unwrap();
+
+        // A second writer must be picked up after the first one closed the pipe.
+        let second_writer = write_to_fifo(path, "third\n");
+        assert_eq!(stream.next().await.unwrap().payload, b"third");
+        second_writer.join().unwrap();
+    }
+
+    #[tokio::test]
+    async fn publish_dead_letter_appends_lines() {
+        let dir = TempDir::new().unwrap();
+        let dead_letter_path = dir.path().join("dlq.jsonl");
+        let broker = PipeBroker::from_options(&PipeOptions {
+            outbound_path: dir.path().join("out.pipe"),
+            dead_letter_path: Some(dead_letter_path.clone()),
+            receipts_path: None,
+            metrics_path: None,
+        });
+
+        broker
+            .publish_dead_letter(Some("1"), b"{\"a\":1}")
+            .await
+            .unwrap();
+        broker
+            .publish_dead_letter(None, b"{\"b\":2}")
+            .await
+            .unwrap();
+
+        let contents = std::fs::read_to_string(dead_letter_path).unwrap();
+        assert_eq!(contents, "{\"a\":1}\n{\"b\":2}\n");
+    }
+}
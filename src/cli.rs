@@ -1,4 +1,5 @@
 use clap::{Parser, Subcommand};
+use ratatoskr::broker::BrokerKind;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -10,12 +11,20 @@ pub struct Cli {
     /// Path to users.toml config file
     #[arg(long, global = true, default_value = "/etc/ratatoskr/users.toml")]
     pub users_file: PathBuf,
+
+    /// Path to config.toml (broker selection and backend options)
+    #[arg(long, global = true, default_value = "/etc/ratatoskr/config.toml")]
+    pub config: PathBuf,
 }
 
 #[derive(Subcommand)]
 pub enum Command {
     /// Run the bot
-    Serve,
+    Serve {
+        /// Broker backend (overrides the config file)
+        #[arg(long, value_enum)]
+        broker: Option<BrokerKind>,
+    },
     /// Manage users
     Users {
         #[command(subcommand)]
@@ -61,6 +70,19 @@ mod tests {
             _ => panic!("expected send command"),
         }
     }
+
+    #[test]
+    fn parse_serve_broker_flag() {
+        let cli = Cli::try_parse_from(["ratatoskr", "serve", "--broker", "pipe"])
+            .expect("expected --broker to parse");
+
+        match cli.command {
+            super::Command::Serve { broker } => {
+                assert_eq!(broker, Some(super::BrokerKind::Pipe));
+            }
+            _ => panic!("expected serve command"),
+        }
+    }
 }
 
 #[derive(Subcommand)]
@@ -1,7 +1,39 @@
+use crate::broker::BrokerKind;
+use crate::broker::kafka::KafkaOptions;
+use crate::broker::memory::MemoryOptions;
+use crate::broker::pipe::PipeOptions;
+use crate::kafka_processing::callback::CallbackOptions;
+use crate::kafka_processing::dispatch::DispatchOptions;
+use crate::kafka_processing::rate_limit::RateLimitOptions;
+use crate::kafka_processing::retry::RetryPolicy;
+use crate::kafka_processing::stream::StreamOptions;
 use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 use std::path::Path;
 
+/// Settings for `ratatoskr serve`, loaded from config.toml.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ServeConfig {
+    /// Broker backend; falls back to pipe mode when `PIPE_OUTBOUND_PATH` is set, Kafka otherwise
+    pub broker: Option<BrokerKind>,
+    #[serde(default)]
+    pub kafka: KafkaOptions,
+    #[serde(default)]
+    pub pipe: PipeOptions,
+    #[serde(default)]
+    pub memory: MemoryOptions,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    #[serde(default)]
+    pub rate_limit: RateLimitOptions,
+    #[serde(default)]
+    pub dispatch: DispatchOptions,
+    #[serde(default)]
+    pub stream: StreamOptions,
+    #[serde(default)]
+    pub callbacks: CallbackOptions,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct UsersConfig {
     #[serde(default)]
@@ -49,6 +81,39 @@ impl UsersConfig {
     }
 }
 
+impl ServeConfig {
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        toml::from_str(&content).with_context(|| format!("Failed to parse {}", path.display()))
+    }
+
+    /// Apply environment variable overrides (`KAFKA_BROKERS`, `KAFKA_TOPIC_PREFIX`,
+    /// `PIPE_OUTBOUND_PATH`) on top of the values read from the config file.
+    pub fn apply_env(&mut self, var: impl Fn(&str) -> Option<String>) {
+        if let Some(brokers) = var("KAFKA_BROKERS") {
+            self.kafka.brokers = brokers;
+        }
+        if let Some(prefix) = var("KAFKA_TOPIC_PREFIX") {
+            self.kafka.topic_prefix = prefix;
+        }
+        if let Some(path) = var("PIPE_OUTBOUND_PATH") {
+            self.pipe.outbound_path = path.into();
+            if self.broker.is_none() {
+                self.broker = Some(BrokerKind::Pipe);
+            }
+        }
+    }
+
+    /// The broker backend to use.
+    pub fn broker_kind(&self) -> BrokerKind {
+        self.broker.unwrap_or_default()
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -93,4 +158,50 @@ system_user = "bob"
         let config: UsersConfig = toml::from_str(toml_str).unwrap();
         assert!(config.users[0].enabled);
     }
+
+    #[test]
+    fn serve_config_defaults_to_kafka() {
+        let config = ServeConfig::load(Path::new("/nonexistent/config.toml")).unwrap();
+        assert_eq!(config.broker_kind(), BrokerKind::Kafka);
+        assert_eq!(config.kafka.brokers, "localhost:9092");
+        assert_eq!(config.kafka.topic_prefix, "ratatoskr");
+    }
+
+    #[test]
+    fn serve_config_parses_broker_sections() {
+        let toml_str = r#"
+broker = "pipe"
+
+[kafka]
+brokers = "kafka:9092"
+
+[pipe]
+outbound_path = "/run/ratatoskr/out.pipe"
+"#;
+        let config: ServeConfig = toml::from_str(toml_str).unwrap();
+        assert_eq!(config.broker_kind(), BrokerKind::Pipe);
+        assert_eq!(config.kafka.brokers, "kafka:9092");
+        assert_eq!(config.kafka.group_id, "ratatoskr");
+        assert_eq!(
+            config.pipe.outbound_path,
+            Path::new("/run/ratatoskr/out.pipe")
+        );
+    }
+
+    #[test]
+    fn pipe_env_selects_pipe_only_without_explicit_broker() {
+        let env = |name: &str| (name == "PIPE_OUTBOUND_PATH").then(|| "/tmp/out.pipe".to_string());
+
+        let mut config = ServeConfig::default();
+        config.apply_env(env);
+        assert_eq!(config.broker_kind(), BrokerKind::Pipe);
+        assert_eq!(config.pipe.outbound_path, Path::new("/tmp/out.pipe"));
+
+        let mut config = ServeConfig {
+            broker: Some(BrokerKind::Kafka),
+            ..Default::default()
+        };
+        config.apply_env(env);
+        assert_eq!(config.broker_kind(), BrokerKind::Kafka);
+    }
 }
@@ -0,0 +1,122 @@
+//! Answering callback queries.
+//!
+//! Telegram shows a progress indicator on a pressed inline button until its
+//! callback query is answered. Backends answer with `AnswerCallbackQuery` to
+//! show a notification, an alert or open a URL; depending on
+//! `[callbacks] auto_answer` Ratatoskr answers the queries they leave alone.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::prelude::{Bot, Requester};
+use tracing::Instrument;
+
+/// When Ratatoskr answers a callback query on its own.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoAnswer {
+    /// Never; the backend answers every query itself.
+    Off,
+    /// As soon as the query arrives, with an empty answer. Answers from the
+    /// backend are dropped, as Telegram would reject them.
+    Immediate,
+    /// With an empty answer, unless the backend answered within `answer_timeout_ms`.
+    #[default]
+    Timeout,
+}
+
+/// Callback query handling (`[callbacks]` section of the config file).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct CallbackOptions {
+    pub auto_answer: AutoAnswer,
+    /// How long the backend has to answer in `timeout` mode
+    pub answer_timeout_ms: u64,
+}
+
+impl Default for CallbackOptions {
+    fn default() -> Self {
+        Self {
+            auto_answer: AutoAnswer::default(),
+            answer_timeout_ms: 5000,
+        }
+    }
+}
+
+/// Callback queries that wait for an answer from the backend.
+#[derive(Default)]
+pub struct CallbackAnswers {
+    options: CallbackOptions,
+    pending: Mutex<HashSet<String>>,
+}
+
+impl CallbackAnswers {
+    pub fn new(options: CallbackOptions) -> Self {
+        Self {
+            options,
+            pending: Mutex::default(),
+        }
+    }
+
+    /// Auto-answer a callback query that just arrived, as configured.
+    pub async fn received(self: &Arc<Self>, bot: &Bot, query_id: &str) {
+        match self.options.auto_answer {
+            AutoAnswer::Off => {}
+            AutoAnswer::Immediate => answer_empty(bot, query_id).await,
+            AutoAnswer::Timeout => {
+                self.pending.lock().unwrap().insert(query_id.to_string());
+                let answers = Arc::clone(self);
+                let bot = bot.clone();
+                let query_id = query_id.to_string();
+                let timeout = Duration::from_millis(self.options.answer_timeout_ms);
+                tokio::spawn(
+                    async move {
+                        tokio::time::sleep(timeout).await;
+                        if answers.take(&query_id) {
+                            tracing::debug!(callback_query_id = %query_id, "Backend did not answer callback query in time");
+                            answer_empty(&bot, &query_id).await;
+                        }
+                    }
+                    .in_current_span(),
+                );
+            }
+        }
+    }
+
+    /// Claim the answer to `query_id` for the backend, so it is not
+    /// auto-answered. Returns false if Ratatoskr has already answered it, in
+    /// which case Telegram would reject the backend's answer.
+    pub(crate) fn claim(&self, query_id: &str) -> bool {
+        match self.options.auto_answer {
+            AutoAnswer::Off => true,
+            AutoAnswer::Immediate => false,
+            AutoAnswer::Timeout => self.take(query_id),
+        }
+    }
+
+    /// Remove `query_id` from the queries waiting for an answer. Returns
+    /// whether it was still waiting.
+    fn take(&self, query_id: &str) -> bool {
+        self.pending.lock().unwrap().remove(query_id)
+    }
+}
+
+async fn answer_empty(bot: &Bot, query_id: &str) {
+    if let Err(e) = bot.answer_callback_query(query_id).await {
+        tracing::warn!(callback_query_id = %query_id, error = %e, "Failed to answer callback query");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn callback_options_parse_from_toml() {
+        let options: CallbackOptions = toml::from_str("auto_answer = \"timeout\"").unwrap();
+        assert_eq!(options.auto_answer, AutoAnswer::Timeout);
+        assert_eq!(options.answer_timeout_ms, 5000);
+        assert_eq!(CallbackOptions::default().auto_answer, AutoAnswer::Timeout);
+    }
+}
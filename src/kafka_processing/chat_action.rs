@@ -0,0 +1,116 @@
+//! Chat actions ("typing…", "sending photo…") that last until the reply is sent.
+//!
+//! Telegram clears a chat action after five seconds. For replies that take
+//! longer, a kept-alive action is re-sent every few seconds until the next
+//! outgoing message for the same chat and thread arrives, or until it times out.
+
+use super::dispatch::LaneKey;
+use super::outgoing::ChatActionKind;
+use super::payload::in_thread;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::prelude::{Bot, ChatId};
+use teloxide::requests::{JsonRequest, Payload};
+use teloxide::types::{Recipient, ThreadId, True};
+use tokio::task::AbortHandle;
+use tokio::time::Instant;
+use tracing::Instrument;
+
+/// Telegram shows an action for five seconds; refresh it a little earlier.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(4);
+
+/// How long an action is kept alive when the message sets no timeout.
+pub const DEFAULT_TIMEOUT_SECS: u64 = 60;
+
+/// The `sendChatAction` payload with our own action kind, as teloxide's
+/// `ChatAction` has no `choose_sticker`.
+#[derive(Debug, Clone, Serialize)]
+pub(crate) struct SendChatAction {
+    chat_id: Recipient,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) message_thread_id: Option<ThreadId>,
+    action: ChatActionKind,
+}
+
+impl Payload for SendChatAction {
+    type Output = True;
+
+    const NAME: &'static str = "sendChatAction";
+}
+
+/// A request showing `action` in `chat_id`.
+pub(crate) fn send_chat_action(
+    bot: &Bot,
+    chat_id: ChatId,
+    action: ChatActionKind,
+) -> JsonRequest<SendChatAction> {
+    JsonRequest::new(
+        bot.clone(),
+        SendChatAction {
+            chat_id: chat_id.into(),
+            message_thread_id: None,
+            action,
+        },
+    )
+}
+
+/// The chat actions that are kept alive, by chat and thread.
+#[derive(Default)]
+pub struct ChatActions {
+    active: Mutex<HashMap<LaneKey, (u64, AbortHandle)>>,
+    next_id: AtomicU64,
+}
+
+impl ChatActions {
+    /// Re-send `action` until `stop` is called for the lane or `timeout`
+    /// expires. Replaces an action that is already kept alive there.
+    pub(crate) fn keep_alive(
+        self: &Arc<Self>,
+        bot: &Bot,
+        lane: LaneKey,
+        action: ChatActionKind,
+        timeout: Duration,
+    ) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        // Held until the task is registered, so it cannot deregister itself first.
+        let mut active = self.active.lock().unwrap();
+        let actions = Arc::clone(self);
+        let bot = bot.clone();
+        let deadline = Instant::now() + timeout;
+        let task = tokio::spawn(
+            async move {
+                loop {
+                    let next = Instant::now() + REFRESH_INTERVAL;
+                    if next >= deadline {
+                        break;
+                    }
+                    tokio::time::sleep_until(next).await;
+                    let request = send_chat_action(&bot, ChatId(lane.chat_id), action);
+                    if let Err(e) = in_thread(request, lane.thread_id).await {
+                        tracing::warn!(error = %e, "Failed to refresh chat action");
+                        break;
+                    }
+                }
+                let mut active = actions.active.lock().unwrap();
+                if active.get(&lane).is_some_and(|(current, _)| *current == id) {
+                    active.remove(&lane);
+                }
+            }
+            .in_current_span(),
+        );
+        if let Some((_, previous)) = active.insert(lane, (id, task.abort_handle())) {
+            previous.abort();
+        }
+    }
+
+    /// Stop the action kept alive in `lane`, if any.
+    pub(crate) fn stop(&self, lane: LaneKey) {
+        if let Some((_, task)) = self.active.lock().unwrap().remove(&lane) {
+            task.abort();
+            tracing::debug!(chat_id = lane.chat_id, thread_id = ?lane.thread_id, "Stopped chat action");
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use crate::kafka_processing::retry::RetryError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Why an outgoing message ended up in the dead-letter queue.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    /// The payload is not a valid `OutgoingMessage`
+    MalformedPayload,
+    /// The message was rejected before reaching Telegram (e.g. missing file)
+    InvalidMessage,
+    /// Telegram rejected the request
+    TelegramApi,
+    /// Telegram asked us to slow down
+    RateLimited,
+    /// The group was upgraded to a supergroup with a new chat ID
+    ChatMigrated,
+    /// The request could not reach Telegram
+    Network,
+    /// Telegram answered with a response we could not parse
+    InvalidResponse,
+    /// A local I/O error, e.g. while uploading a file
+    Io,
+    /// Ratatoskr panicked while handling the message
+    Panicked,
+}
+
+impl ErrorKind {
+    /// Classify an error returned by `handle_outgoing_message`.
+    pub fn classify(error: &(dyn std::error::Error + 'static)) -> Self {
+        let request_error = match RetryError::find(error) {
+            Some(retry_error) => Some(&retry_error.source),
+            None => error.downcast_ref::<teloxide::RequestError>(),
+        };
+        match request_error {
+            Some(teloxide::RequestError::Api(_)) => Self::TelegramApi,
+            Some(teloxide::RequestError::RetryAfter(_)) => Self::RateLimited,
+            Some(teloxide::RequestError::MigrateToChatId(_)) => Self::ChatMigrated,
+            Some(teloxide::RequestError::Network(_)) => Self::Network,
+            Some(teloxide::RequestError::InvalidJson { .. }) => Self::InvalidResponse,
+            Some(teloxide::RequestError::Io(_)) => Self::Io,
+            // Everything else is raised by our own validation.
+            None => Self::InvalidMessage,
+        }
+    }
+}
+
+/// An outgoing message that could not be delivered, as published to the
+/// dead-letter topic (`{prefix}.dlq`).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeadLetterRecord {
+    /// Trace ID of the original message, if it could be read
+    pub trace_id: Option<Uuid>,
+    /// Target chat of the original message, if it could be read
+    pub chat_id: Option<i64>,
+    pub error_kind: ErrorKind,
+    pub error_message: String,
+    /// Number of delivery attempts made before giving up
+    pub attempts: u32,
+    /// IDs of the messages already sent when a message in several parts
+    /// failed; replaying the payload sends them again
+    #[serde(default)]
+    pub sent_message_ids: Vec<i32>,
+    /// The payload exactly as received, so that it can be replayed
+    pub original_payload: String,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl DeadLetterRecord {
+    /// Build a record for `payload`, picking up the trace ID and chat ID from
+    /// the payload when they are present.
+    pub fn new(payload: &[u8], error_kind: ErrorKind, error_message: impl Into<String>) -> Self {
+        let json = serde_json::from_slice::<serde_json::Value>(payload).ok();
+        let trace_id = json
+            .as_ref()
+            .and_then(|v| v.get("trace_id")?.as_str()?.parse().ok());
+        let chat_id = json.as_ref().and_then(|v| {
+            v.get("target")
+                .and_then(|target| target.get("chat_id"))
+                .or_else(|| v.get("chat_id"))?
+                .as_i64()
+        });
+
+        Self {
+            trace_id,
+            chat_id,
+            error_kind,
+            error_message: error_message.into(),
+            attempts: 1,
+            sent_message_ids: Vec::new(),
+            original_payload: String::from_utf8_lossy(payload).into_owned(),
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn with_attempts(mut self, attempts: u32) -> Self {
+        self.attempts = attempts;
+        self
+    }
+
+    pub fn with_sent_message_ids(mut self, message_ids: Vec<i32>) -> Self {
+        self.sent_message_ids = message_ids;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn classify_distinguishes_telegram_and_validation_errors() {
+        let api = teloxide::RequestError::Api(teloxide::ApiError::MessageTextIsEmpty);
+        assert_eq!(ErrorKind::classify(&api), ErrorKind::TelegramApi);
+
+        let exhausted = RetryError {
+            attempts: 3,
+            source: teloxide::RequestError::RetryAfter(teloxide::types::Seconds::from_seconds(5)),
+        };
+        assert_eq!(ErrorKind::classify(&exhausted), ErrorKind::RateLimited);
+
+        let validation: Box<dyn std::error::Error + Send + Sync> =
+            "Image file not found: /tmp/x.jpg".into();
+        assert_eq!(
+            ErrorKind::classify(validation.as_ref()),
+            ErrorKind::InvalidMessage
+        );
+    }
+
+    #[test]
+    fn new_reads_trace_and_chat_id_from_payload() {
+        let payload =
+            br#"{"trace_id":"00000000-0000-0000-0000-000000000001","target":{"chat_id":42}}"#;
+        let record = DeadLetterRecord::new(payload, ErrorKind::TelegramApi, "Bad Request");
+        assert_eq!(record.trace_id, Some(Uuid::from_u128(1)));
+        assert_eq!(record.chat_id, Some(42));
+        assert_eq!(record.original_payload.as_bytes(), payload);
+
+        let record = DeadLetterRecord::new(b"not json", ErrorKind::MalformedPayload, "eof");
+        assert_eq!(record.trace_id, None);
+        assert_eq!(record.chat_id, None);
+    }
+}
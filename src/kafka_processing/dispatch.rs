@@ -0,0 +1,282 @@
+use futures_util::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+
+/// Outgoing dispatch settings (`[dispatch]` section of the config file).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct DispatchOptions {
+    /// Outgoing messages handled at the same time, across different chats
+    pub concurrency: usize,
+    /// Messages read from the broker but not yet handled; the consumer stops
+    /// reading while this many are queued
+    pub max_pending: usize,
+}
+
+impl Default for DispatchOptions {
+    fn default() -> Self {
+        Self {
+            concurrency: 8,
+            max_pending: 256,
+        }
+    }
+}
+
+/// Messages for the same lane are handled strictly in order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct LaneKey {
+    pub chat_id: i64,
+    pub thread_id: Option<i32>,
+}
+
+type Task = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A queued job. `ready` is awaited without holding a worker, so a lane that
+/// waits (e.g. for the rate limiter) does not keep other lanes from running.
+struct Job {
+    ready: Task,
+    run: Task,
+}
+
+struct Inner {
+    /// Jobs waiting behind the running job of each busy lane
+    lanes: Mutex<HashMap<LaneKey, VecDeque<Job>>>,
+    running: Semaphore,
+    pending: Arc<Semaphore>,
+    max_pending: u32,
+}
+
+/// Runs jobs for different lanes in parallel, up to `concurrency` at a time,
+/// while jobs within a lane run one after another in submission order.
+/// Jobs that are not ready yet do not count against `concurrency`.
+///
+/// Each busy lane is drained by its own task, which removes the lane once it
+/// is empty.
+#[derive(Clone)]
+pub struct Dispatcher {
+    inner: Arc<Inner>,
+}
+
+impl Dispatcher {
+    pub fn new(options: &DispatchOptions) -> Self {
+        let max_pending = options.max_pending.clamp(1, Semaphore::MAX_PERMITS) as u32;
+        Self {
+            inner: Arc::new(Inner {
+                lanes: Mutex::new(HashMap::new()),
+                running: Semaphore::new(options.concurrency.max(1)),
+                pending: Arc::new(Semaphore::new(max_pending as usize)),
+                max_pending,
+            }),
+        }
+    }
+
+    /// Queue `job` behind earlier jobs of the same lane, waiting first while
+    /// `max_pending` jobs are already queued.
+    pub async fn dispatch(&self, key: LaneKey, job: impl Future<Output = ()> + Send + 'static) {
+        self.dispatch_when(key, async {}, job).await;
+    }
+
+    /// Like [`Dispatcher::dispatch`], but `job` only takes a worker once
+    /// `ready` has completed. Later jobs of the lane still wait for both.
+    pub async fn dispatch_when(
+        &self,
+        key: LaneKey,
+        ready: impl Future<Output = ()> + Send + 'static,
+        job: impl Future<Output = ()> + Send + 'static,
+    ) {
+        let permit = self
+            .inner
+            .pending
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("dispatcher semaphore is never closed");
+        let job = Job {
+            ready: Box::pin(ready),
+            run: Box::pin(async move {
+                if AssertUnwindSafe(job).catch_unwind().await.is_err() {
+                    tracing::error!(chat_id = key.chat_id, thread_id = ?key.thread_id, "Outgoing message handler panicked");
+                }
+                drop(permit);
+            }),
+        };
+
+        let mut lanes = self.inner.lanes.lock().unwrap();
+        match lanes.get_mut(&key) {
+            Some(queue) => {
+                queue.push_back(job);
+                tracing::debug!(chat_id = key.chat_id, thread_id = ?key.thread_id, lane_depth = queue.len(), "Queued outgoing message behind its chat");
+            }
+            None => {
+                lanes.insert(key, VecDeque::new());
+                tokio::spawn(run_lane(self.inner.clone(), key, job));
+            }
+        }
+    }
+
+    /// Wait until every dispatched job has finished.
+    pub async fn wait_idle(&self) {
+        let _all = self
+            .inner
+            .pending
+            .acquire_many(self.inner.max_pending)
+            .await
+            .expect("dispatcher semaphore is never closed");
+    }
+
+    /// Number of lanes with a running job.
+    pub fn busy_lanes(&self) -> usize {
+        self.inner.lanes.lock().unwrap().len()
+    }
+}
+
+async fn run_lane(inner: Arc<Inner>, key: LaneKey, mut job: Job) {
+    loop {
+        job.ready.await;
+        {
+            let _running = inner
+                .running
+                .acquire()
+                .await
+                .expect("dispatcher semaphore is never closed");
+            job.run.await;
+        }
+
+        let mut lanes = inner.lanes.lock().unwrap();
+        match lanes.get_mut(&key).and_then(VecDeque::pop_front) {
+            Some(next) => job = next,
+            None => {
+                lanes.remove(&key);
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::sync::{Notify, mpsc};
+
+    fn lane(chat_id: i64) -> LaneKey {
+        LaneKey {
+            chat_id,
+            thread_id: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn jobs_in_a_lane_run_in_order() {
+        let dispatcher = Dispatcher::new(&DispatchOptions::default());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        for i in 0..5u64 {
+            let tx = tx.clone();
+            dispatcher
+                .dispatch(lane(1), async move {
+                    // Later jobs finish faster; order must still hold.
+                    tokio::time::sleep(Duration::from_millis(10 * (5 - i))).await;
+                    tx.send(i).unwrap();
+                })
+                .await;
+        }
+        dispatcher.wait_idle().await;
+        drop(tx);
+
+        let mut order = Vec::new();
+        while let Some(i) = rx.recv().await {
+            order.push(i);
+        }
+        assert_eq!(order, vec![0, 1, 2, 3, 4]);
+        assert_eq!(dispatcher.busy_lanes(), 0);
+    }
+
+    #[tokio::test]
+    async fn a_blocked_lane_does_not_stall_other_lanes() {
+        let dispatcher = Dispatcher::new(&DispatchOptions::default());
+        let unblock = Arc::new(Notify::new());
+
+        let waiting = unblock.clone();
+        dispatcher
+            .dispatch(lane(1), async move { waiting.notified().await })
+            .await;
+        // Same chat, different forum thread: a separate lane.
+        let other_thread = LaneKey {
+            chat_id: 1,
+            thread_id: Some(7),
+        };
+        let notify = unblock.clone();
+        dispatcher
+            .dispatch(other_thread, async move { notify.notify_one() })
+            .await;
+
+        tokio::time::timeout(Duration::from_secs(5), dispatcher.wait_idle())
+            .await
+            .expect("lane 1 should be unblocked by the other lane");
+    }
+
+    #[tokio::test]
+    async fn concurrency_limits_parallel_jobs() {
+        let dispatcher = Dispatcher::new(&DispatchOptions {
+            concurrency: 1,
+            ..Default::default()
+        });
+        let unblock = Arc::new(Notify::new());
+
+        let waiting = unblock.clone();
+        dispatcher
+            .dispatch(lane(1), async move { waiting.notified().await })
+            .await;
+        let notify = unblock.clone();
+        dispatcher
+            .dispatch(lane(2), async move { notify.notify_one() })
+            .await;
+
+        // With a single worker, lane 2 can never run while lane 1 waits for it.
+        let idle = tokio::time::timeout(Duration::from_millis(100), dispatcher.wait_idle()).await;
+        assert!(idle.is_err());
+        unblock.notify_one();
+        dispatcher.wait_idle().await;
+    }
+
+    #[tokio::test]
+    async fn jobs_waiting_to_be_ready_do_not_take_a_worker() {
+        let dispatcher = Dispatcher::new(&DispatchOptions {
+            concurrency: 1,
+            ..Default::default()
+        });
+        let unblock = Arc::new(Notify::new());
+        let (tx, mut rx) = mpsc::unbounded_channel();
+
+        let waiting = unblock.clone();
+        let ran = tx.clone();
+        dispatcher
+            .dispatch_when(
+                lane(1),
+                async move { waiting.notified().await },
+                async move {
+                    ran.send(1).unwrap();
+                },
+            )
+            .await;
+        let notify = unblock.clone();
+        dispatcher
+            .dispatch(lane(2), async move {
+                tx.send(2).unwrap();
+                notify.notify_one();
+            })
+            .await;
+
+        tokio::time::timeout(Duration::from_secs(5), dispatcher.wait_idle())
+            .await
+            .expect("lane 2 should run while lane 1 is not ready");
+        assert_eq!(rx.recv().await, Some(2));
+        assert_eq!(rx.recv().await, Some(1));
+    }
+}
@@ -0,0 +1,79 @@
+//! Turning a `MediaSource` from an outgoing message into a teloxide `InputFile`.
+
+use super::outgoing::MediaSource;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use std::path::Path;
+use teloxide::types::InputFile;
+
+/// Resolve `source` into the file to upload. `label` names the file in error
+/// messages, e.g. "Image".
+pub(crate) fn input_file(source: &MediaSource, label: &str) -> Result<InputFile, String> {
+    match source {
+        MediaSource::Path { path } => {
+            if !Path::new(path).exists() {
+                return Err(format!("{label} file not found: {path}"));
+            }
+            Ok(InputFile::file(path))
+        }
+        MediaSource::Url { url } => reqwest::Url::parse(url)
+            .map(InputFile::url)
+            .map_err(|e| format!("{label} URL is invalid: {url}: {e}")),
+        MediaSource::FileId { file_id } => Ok(InputFile::file_id(file_id.clone())),
+        MediaSource::Base64 { data, filename } => STANDARD
+            .decode(data.trim())
+            .map(|bytes| InputFile::memory(bytes).file_name(filename.clone()))
+            .map_err(|e| format!("{label} data is not valid base64: {e}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source(json: serde_json::Value) -> MediaSource {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn plain_strings_are_paths() {
+        assert_eq!(
+            source(serde_json::json!("/tmp/cat.jpg")),
+            MediaSource::Path {
+                path: "/tmp/cat.jpg".to_string()
+            }
+        );
+        assert_eq!(
+            source(serde_json::json!({ "type": "file_id", "file_id": "AgAC" })),
+            MediaSource::FileId {
+                file_id: "AgAC".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_sources_are_rejected_before_sending() {
+        let missing = source(serde_json::json!("/definitely/not/here.jpg"));
+        assert_eq!(
+            input_file(&missing, "Image").unwrap_err(),
+            "Image file not found: /definitely/not/here.jpg"
+        );
+
+        let bad_url = source(serde_json::json!({ "type": "url", "url": "not a url" }));
+        assert!(input_file(&bad_url, "Image").is_err());
+
+        let bad_data = source(serde_json::json!({
+            "type": "base64",
+            "data": "***",
+            "filename": "chart.png"
+        }));
+        assert!(input_file(&bad_data, "Image").is_err());
+
+        let good_data = source(serde_json::json!({
+            "type": "base64",
+            "data": "aGVsbG8=",
+            "filename": "hello.txt"
+        }));
+        assert!(input_file(&good_data, "Document").is_ok());
+    }
+}
@@ -1,51 +1,450 @@
-use self::outgoing::{OutgoingMessage, OutgoingMessageType};
-use crate::broker::MessageBroker;
-use crate::utils::{create_markup, create_reply_keyboard, format_telegram_markdown};
-use futures_util::StreamExt;
-use std::path::Path;
+use self::callback::CallbackAnswers;
+use self::chat_action::{ChatActions, DEFAULT_TIMEOUT_SECS, send_chat_action};
+use self::dead_letter::{DeadLetterRecord, ErrorKind};
+use self::dispatch::{DispatchOptions, Dispatcher, LaneKey};
+use self::outgoing::{
+    ButtonInfo, MediaGroupItem, MediaGroupKind, OutgoingMessage, OutgoingMessageType,
+    ReplyKeyboardMarkup, ReplyMarkupInfo, SendOptions,
+};
+use self::payload::{CaptionPayload, MarkupPayload, RawMarkup, in_thread, prepare};
+use self::rate_limit::{RateLimitOptions, RateLimiter};
+use self::receipt::DeliveryReceipt;
+use self::retry::{RetryError, RetryPolicy};
+use self::split::{TEXT_MAX_LEN, split_caption, split_html, split_text, strip_html};
+use self::stream::{StreamOptions, StreamRegistry};
+use crate::broker::{Delivery, MessageBroker};
+use crate::utils::{
+    Markup, create_chat_permissions, create_markup, create_reply_keyboard, create_reply_markup,
+    format_telegram_markdown, reaction_from_string,
+};
+use futures_util::{FutureExt, StreamExt};
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::panic::AssertUnwindSafe;
 use std::sync::Arc;
+use std::time::Duration;
 use teloxide::{
+    ApiError, RequestError,
     payloads::{
-        EditMessageReplyMarkupSetters, EditMessageTextSetters, SendAnimationSetters,
-        SendAudioSetters, SendDocumentSetters, SendMessageSetters, SendPhotoSetters,
-        SendStickerSetters, SendVideoNoteSetters, SendVideoSetters, SendVoiceSetters,
+        AnswerCallbackQuerySetters, BanChatMemberSetters, CopyMessageSetters, CopyMessagesSetters,
+        EditMessageCaptionSetters, EditMessageLiveLocationSetters, EditMessageMediaSetters,
+        EditMessageTextSetters, ForwardMessageSetters, PinChatMessageSetters,
+        RestrictChatMemberSetters, SendAnimationSetters, SendAudioSetters, SendContactSetters,
+        SendDiceSetters, SendDocumentSetters, SendLocationSetters, SendMessageSetters,
+        SendPhotoSetters, SendPollSetters, SendStickerSetters, SendVenueSetters,
+        SendVideoNoteSetters, SendVideoSetters, SendVoiceSetters, SetMessageReactionSetters,
+        UnbanChatMemberSetters, UnpinChatMessageSetters,
     },
     prelude::{Bot, ChatId, Requester},
-    types::{InputFile, ParseMode},
+    requests::{HasPayload, JsonRequest, Output, Request},
+    types::{
+        DiceEmoji, InputFile, InputMedia, InputMediaAudio, InputMediaDocument, InputMediaPhoto,
+        InputMediaVideo, LivePeriod, ParseMode, PollType, ReactionType, ReplyMarkup, UserId,
+    },
 };
 use tracing::Instrument;
 
+pub mod callback;
+pub mod chat_action;
+pub mod dead_letter;
+pub mod dispatch;
+mod media;
 pub mod outgoing;
+mod payload;
+pub mod rate_limit;
+pub mod receipt;
+pub mod retry;
+mod split;
+pub mod stream;
+
+/// Shared state for delivering outgoing messages to Telegram.
+#[derive(Clone)]
+pub struct OutgoingContext {
+    pub bot: Bot,
+    pub retry: RetryPolicy,
+    pub rate_limiter: Arc<RateLimiter>,
+    pub dispatch: DispatchOptions,
+    pub streams: Arc<StreamRegistry>,
+    pub callback_answers: Arc<CallbackAnswers>,
+    pub chat_actions: Arc<ChatActions>,
+}
+
+impl OutgoingContext {
+    /// Create a context for `bot` with the default policies.
+    pub fn new(bot: Bot) -> Self {
+        Self {
+            bot,
+            retry: RetryPolicy::default(),
+            rate_limiter: Arc::default(),
+            dispatch: DispatchOptions::default(),
+            streams: Arc::default(),
+            callback_answers: Arc::default(),
+            chat_actions: Arc::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    pub fn with_rate_limits(mut self, options: RateLimitOptions) -> Self {
+        self.rate_limiter = Arc::new(RateLimiter::new(options));
+        self
+    }
+
+    pub fn with_dispatch(mut self, options: DispatchOptions) -> Self {
+        self.dispatch = options;
+        self
+    }
+
+    pub fn with_streaming(mut self, options: StreamOptions) -> Self {
+        self.streams = Arc::new(StreamRegistry::new(options));
+        self
+    }
+
+    /// Share the callback queries waiting for an answer with the update handlers.
+    pub fn with_callback_answers(mut self, answers: Arc<CallbackAnswers>) -> Self {
+        self.callback_answers = answers;
+        self
+    }
+}
 
-/// Simple helper to try sending with markdown, falling back to plain text if it fails
+/// Retry a formatted send as plain text when Telegram cannot parse its
+/// entities. Every other error is returned unchanged.
 async fn try_send_with_fallback<T, F, Fut>(
-    markdown_result: Result<T, teloxide::RequestError>,
+    formatted_result: Result<T, RetryError>,
     fallback_fn: F,
     message_type: &str,
-) -> Result<T, teloxide::RequestError>
+) -> Result<T, RetryError>
 where
     F: FnOnce() -> Fut,
-    Fut: std::future::Future<Output = Result<T, teloxide::RequestError>>,
+    Fut: std::future::Future<Output = Result<T, RetryError>>,
 {
-    match markdown_result {
-        Ok(result) => Ok(result),
-        Err(_) => {
+    match formatted_result {
+        Err(RetryError {
+            source: RequestError::Api(ApiError::CantParseEntities(description)),
+            ..
+        }) => {
             tracing::warn!(
+                error = %description,
                 "Failed to send {} with formatting, retrying with plain text",
                 message_type
             );
             fallback_fn().await
         }
+        result => result,
     }
 }
 
+/// Send a media message with an HTML `caption`, resending it with the caption
+/// as plain text when Telegram cannot parse its entities.
+async fn send_captioned<R>(
+    retry: &RetryPolicy,
+    mut request: R,
+    caption: Option<String>,
+    message_type: &str,
+) -> Result<Output<R>, RetryError>
+where
+    R: Request<Err = RequestError>,
+    R::Payload: CaptionPayload,
+{
+    let Some(caption) = caption else {
+        return retry.send(&request).await;
+    };
+    request
+        .payload_mut()
+        .set_caption(Some(caption.clone()), Some(ParseMode::Html));
+    try_send_with_fallback(
+        retry.send(&request).await,
+        || async move {
+            request
+                .payload_mut()
+                .set_caption(Some(strip_html(&caption)), None);
+            retry.send(&request).await
+        },
+        message_type,
+    )
+    .await
+}
+
+/// Send `request` with `markup`. Raw markup is spliced into the payload's
+/// JSON, so `request` is then sent as JSON whatever its usual encoding.
+async fn send_with_markup<R>(
+    ctx: &OutgoingContext,
+    mut request: R,
+    markup: Option<Markup<<R::Payload as MarkupPayload>::Markup>>,
+) -> Result<Output<R>, RetryError>
+where
+    R: Request<Err = RequestError>,
+    R::Payload: MarkupPayload + Serialize + Clone + 'static,
+    Output<R>: DeserializeOwned,
+{
+    match markup {
+        Some(Markup::Raw(markup)) => {
+            let payload = RawMarkup::new(request.payload_ref().clone(), markup);
+            ctx.retry
+                .send(&JsonRequest::new(ctx.bot.clone(), payload))
+                .await
+        }
+        Some(Markup::Typed(markup)) => {
+            request.payload_mut().set_reply_markup(markup);
+            ctx.retry.send(&request).await
+        }
+        None => ctx.retry.send(&request).await,
+    }
+}
+
+/// Put the raw `markup` of a media message on it once it is sent. Media is
+/// uploaded as multipart form data, which cannot carry raw markup; typed
+/// markup is sent with the media and ignored here.
+async fn add_raw_markup<M>(
+    ctx: &OutgoingContext,
+    chat_id: ChatId,
+    message_id: i32,
+    markup: Option<Markup<M>>,
+) -> Result<(), PartialSend> {
+    let Some(Markup::Raw(markup)) = markup else {
+        return Ok(());
+    };
+    ctx.rate_limiter.acquire(chat_id.0).await;
+    let edit = ctx
+        .bot
+        .edit_message_reply_markup(chat_id, teloxide::types::MessageId(message_id));
+    match send_with_markup(ctx, edit, Some(Markup::Raw(markup))).await {
+        Ok(_) => Ok(()),
+        Err(source) => Err(PartialSend {
+            message_ids: vec![message_id],
+            source,
+        }),
+    }
+}
+
+/// The reply markup for a message. `buttons` and `reply_keyboard` are
+/// shorthands for an inline or reply keyboard `reply_markup`; at most one of
+/// the three may be set.
+fn reply_markup(
+    reply_markup: &Option<ReplyMarkupInfo>,
+    buttons: &Option<Vec<Vec<ButtonInfo>>>,
+    reply_keyboard: &Option<ReplyKeyboardMarkup>,
+) -> Result<Option<Markup<ReplyMarkup>>, String> {
+    match (reply_markup, buttons, reply_keyboard) {
+        (Some(markup), None, None) => Ok(Some(create_reply_markup(markup))),
+        (None, _, None) => {
+            Ok(create_markup(buttons).map(|markup| markup.map(ReplyMarkup::InlineKeyboard)))
+        }
+        (None, None, Some(_)) => Ok(create_reply_keyboard(reply_keyboard)
+            .map(|keyboard| Markup::Typed(ReplyMarkup::Keyboard(keyboard)))),
+        _ => Err("Only one of reply_markup, buttons and reply_keyboard can be set".to_string()),
+    }
+}
+
+/// Render `text` the way its `parse_mode` asks: with a parse mode, Markdown is
+/// converted to Telegram HTML; without one the text is sent as is.
+fn render_text(text: &str, parse_mode: &Option<String>) -> (String, Option<ParseMode>) {
+    match parse_mode.as_deref() {
+        None => (text.to_string(), None),
+        Some(parse_mode) => {
+            let parse_mode = match parse_mode {
+                "HTML" => Some(ParseMode::Html),
+                "Markdown" => Some(ParseMode::Html), // Convert markdown to HTML
+                _ => None,
+            };
+            (format_telegram_markdown(text), parse_mode)
+        }
+    }
+}
+
+/// Render a caption to Telegram HTML and split it into the part that fits on
+/// the media and the overflow.
+fn caption_parts(caption: &Option<String>) -> (Option<String>, Vec<String>) {
+    match caption {
+        Some(caption) => {
+            let (caption, overflow) = split_caption(&format_telegram_markdown(caption));
+            (Some(caption), overflow)
+        }
+        None => (None, Vec::new()),
+    }
+}
+
+/// A message sent in several parts that failed after some of them were sent.
+#[derive(Debug)]
+struct PartialSend {
+    /// IDs of the messages sent before the failure
+    message_ids: Vec<i32>,
+    source: RetryError,
+}
+
+impl std::fmt::Display for PartialSend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl std::error::Error for PartialSend {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Send the parts of a split text as consecutive messages. Only the first one
+/// uses the reply and effect from `options`, and only the last one gets
+/// `markup`. With a parse mode, a part Telegram cannot parse is resent as plain
+/// text.
+async fn send_text_parts(
+    ctx: &OutgoingContext,
+    chat_id: ChatId,
+    thread_id: Option<i32>,
+    parts: &[String],
+    parse_mode: Option<ParseMode>,
+    options: &SendOptions,
+    markup: Option<Markup<ReplyMarkup>>,
+) -> Result<Vec<i32>, PartialSend> {
+    let mut message_ids = Vec::with_capacity(parts.len());
+    for (i, part) in parts.iter().enumerate() {
+        if i > 0 {
+            ctx.rate_limiter.acquire(chat_id.0).await;
+        }
+        let options = if i == 0 {
+            options.clone()
+        } else {
+            options.follow_up()
+        };
+        let last = i + 1 == parts.len();
+        let send = |text: String, parse_mode: Option<ParseMode>| {
+            let mut msg = prepare(ctx.bot.send_message(chat_id, text), thread_id, &options);
+            if let Some(parse_mode) = parse_mode {
+                msg = msg.parse_mode(parse_mode);
+            }
+            send_with_markup(ctx, msg, markup.clone().filter(|_| last))
+        };
+        let sent = match parse_mode {
+            Some(parse_mode) => {
+                try_send_with_fallback(
+                    send(part.clone(), Some(parse_mode)).await,
+                    || send(strip_html(part), None),
+                    "text message",
+                )
+                .await
+            }
+            None => send(part.clone(), None).await,
+        };
+        match sent {
+            Ok(sent) => message_ids.push(sent.id.0),
+            Err(source) => {
+                return Err(PartialSend {
+                    message_ids,
+                    source,
+                });
+            }
+        }
+    }
+    Ok(message_ids)
+}
+
+/// Send the part of a caption that did not fit on the media as text messages
+/// after it, returning the IDs of the media message `sent` and the overflow.
+/// Without overflow, raw `markup` is added to the media message instead.
+async fn send_caption_overflow(
+    ctx: &OutgoingContext,
+    chat_id: ChatId,
+    thread_id: Option<i32>,
+    sent: i32,
+    overflow: &[String],
+    options: &SendOptions,
+    markup: Option<Markup<ReplyMarkup>>,
+) -> Result<Vec<i32>, PartialSend> {
+    let mut message_ids = vec![sent];
+    if overflow.is_empty() {
+        add_raw_markup(ctx, chat_id, sent, markup).await?;
+        return Ok(message_ids);
+    }
+    tracing::info!(parts = overflow.len(), "Sending caption overflow as text");
+    ctx.rate_limiter.acquire(chat_id.0).await;
+    let sent = send_text_parts(
+        ctx,
+        chat_id,
+        thread_id,
+        overflow,
+        Some(ParseMode::Html),
+        &options.follow_up(),
+        markup,
+    )
+    .await;
+    match sent {
+        Ok(overflow_ids) => {
+            message_ids.extend(overflow_ids);
+            Ok(message_ids)
+        }
+        Err(mut e) => {
+            message_ids.append(&mut e.message_ids);
+            e.message_ids = message_ids;
+            Err(e)
+        }
+    }
+}
+
+/// Build the album for a media group. Captions with a parse mode are rendered
+/// to Telegram HTML unless `formatted` is false.
+fn media_group(items: &[MediaGroupItem], files: &[InputFile], formatted: bool) -> Vec<InputMedia> {
+    items
+        .iter()
+        .zip(files.iter().cloned())
+        .map(|(item, file)| {
+            let (caption, parse_mode) = match (&item.caption, &item.parse_mode) {
+                (Some(caption), Some(_)) if formatted => (
+                    Some(format_telegram_markdown(caption)),
+                    Some(ParseMode::Html),
+                ),
+                (caption, _) => (caption.clone(), None),
+            };
+            match item.kind {
+                MediaGroupKind::Photo => InputMedia::Photo(InputMediaPhoto {
+                    caption,
+                    parse_mode,
+                    ..InputMediaPhoto::new(file)
+                }),
+                MediaGroupKind::Video => InputMedia::Video(InputMediaVideo {
+                    caption,
+                    parse_mode,
+                    ..InputMediaVideo::new(file)
+                }),
+                MediaGroupKind::Document => InputMedia::Document(InputMediaDocument {
+                    caption,
+                    parse_mode,
+                    ..InputMediaDocument::new(file)
+                }),
+                MediaGroupKind::Audio => InputMedia::Audio(InputMediaAudio {
+                    caption,
+                    parse_mode,
+                    ..InputMediaAudio::new(file)
+                }),
+            }
+        })
+        .collect()
+}
+
+/// What an outgoing message produced in Telegram.
+struct Handled {
+    /// IDs of the messages sent or acted on
+    message_ids: Vec<i32>,
+    /// ID of the poll sent by a `PollMessage`
+    poll_id: Option<String>,
+}
+
+/// Deliver `message` to Telegram, returning the IDs of the messages it sent or
+/// acted on.
 async fn handle_outgoing_message(
-    bot: &Bot,
+    ctx: &OutgoingContext,
     message: OutgoingMessage,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<Handled, Box<dyn std::error::Error + Send + Sync>> {
+    let bot = &ctx.bot;
+    let retry = &ctx.retry;
     let chat_id = ChatId(message.target.chat_id);
+    let thread_id = message.target.thread_id;
+    let mut poll_id = None;
 
-    match message.message_type {
+    let message_ids = match message.message_type {
         OutgoingMessageType::TextMessage(data) => {
             tracing::info!(text_length = %data.text.len(), has_buttons = %data.buttons.is_some(), "Sending text message to Telegram");
 
@@ -62,132 +461,77 @@ async fn handle_outgoing_message(
             });
 
             // Try with markdown first, fallback to plain text if parsing fails
-            if data.parse_mode.is_some() {
-                let formatted_text = format_telegram_markdown(&data.text);
+            let (formatted_text, parse_mode) = render_text(&data.text, &data.parse_mode);
+            let parts = if data.parse_mode.is_some() {
                 tracing::debug!(
                     original_length = %data.text.len(),
                     formatted_length = %formatted_text.len(),
                     "Formatted text for sending"
                 );
                 tracing::trace!(original_text = %data.text, formatted_text = %formatted_text, "Text formatting details");
-                let mut msg_to_send = bot.send_message(chat_id, formatted_text);
-
-                if let Some(parse_mode) = &data.parse_mode {
-                    msg_to_send = match parse_mode.as_str() {
-                        "HTML" => msg_to_send.parse_mode(ParseMode::Html),
-                        "Markdown" => msg_to_send.parse_mode(ParseMode::Html), // Convert markdown to HTML
-                        _ => msg_to_send,
-                    };
-                }
-
-                if let Some(markup) = create_markup(&organized_buttons) {
-                    msg_to_send = msg_to_send.reply_markup(markup);
-                }
-
-                if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                    msg_to_send = msg_to_send.reply_markup(reply_keyboard);
-                }
-
-                try_send_with_fallback(
-                    msg_to_send.await,
-                    || async {
-                        let mut plain_msg = bot.send_message(chat_id, &data.text);
-                        if let Some(markup) = create_markup(&organized_buttons) {
-                            plain_msg = plain_msg.reply_markup(markup);
-                        }
-                        if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                            plain_msg = plain_msg.reply_markup(reply_keyboard);
-                        }
-                        plain_msg.await
-                    },
-                    "text message",
-                )
-                .await?;
+                split_html(&formatted_text, TEXT_MAX_LEN)
             } else {
                 // No parse mode, send as plain text
-                let mut msg_to_send = bot.send_message(chat_id, &data.text);
-                if let Some(markup) = create_markup(&organized_buttons) {
-                    msg_to_send = msg_to_send.reply_markup(markup);
-                }
-                if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                    msg_to_send = msg_to_send.reply_markup(reply_keyboard);
-                }
-                msg_to_send.await?;
+                split_text(&data.text, TEXT_MAX_LEN)
+            };
+            if parts.len() > 1 {
+                tracing::info!(parts = parts.len(), "Splitting long text message");
             }
+
+            send_text_parts(
+                ctx,
+                chat_id,
+                thread_id,
+                &parts,
+                parse_mode,
+                &data.options,
+                reply_markup(&data.reply_markup, &organized_buttons, &data.reply_keyboard)?,
+            )
+            .await?
         }
 
         OutgoingMessageType::ImageMessage(data) => {
-            tracing::info!(image_path = %data.image_path, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending image message to Telegram");
-
-            if !Path::new(&data.image_path).exists() {
-                return Err(format!("Image file not found: {}", data.image_path).into());
-            }
-
-            let input_file = InputFile::file(&data.image_path);
-
-            // Try with markdown caption first, fallback to plain text if parsing fails
-            if let Some(caption) = &data.caption {
-                let formatted_caption = format_telegram_markdown(caption);
-                let mut msg_to_send = bot
-                    .send_photo(chat_id, input_file.clone())
-                    .caption(formatted_caption)
-                    .parse_mode(ParseMode::Html);
-
-                if let Some(markup) = create_markup(&data.buttons) {
-                    msg_to_send = msg_to_send.reply_markup(markup);
-                }
-
-                if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                    msg_to_send = msg_to_send.reply_markup(reply_keyboard);
-                }
-
-                try_send_with_fallback(
-                    msg_to_send.await,
-                    || async {
-                        let mut plain_msg =
-                            bot.send_photo(chat_id, input_file.clone()).caption(caption);
-                        if let Some(markup) = create_markup(&data.buttons) {
-                            plain_msg = plain_msg.reply_markup(markup);
-                        }
-                        if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                            plain_msg = plain_msg.reply_markup(reply_keyboard);
-                        }
-                        plain_msg.await
-                    },
-                    "image message",
-                )
-                .await?;
-            } else {
-                // No caption, send without formatting
-                let mut msg_to_send = bot.send_photo(chat_id, input_file);
+            tracing::info!(image = %data.image, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending image message to Telegram");
 
-                if let Some(markup) = create_markup(&data.buttons) {
-                    msg_to_send = msg_to_send.reply_markup(markup);
-                }
+            let input_file = media::input_file(&data.image, "Image")?;
+            let mut msg_to_send = prepare(
+                bot.send_photo(chat_id, input_file),
+                thread_id,
+                &data.options,
+            );
 
-                if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                    msg_to_send = msg_to_send.reply_markup(reply_keyboard);
-                }
+            let (caption, overflow) = caption_parts(&data.caption);
 
-                msg_to_send.await?;
+            // Buttons go on the last message
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            if let Some(Markup::Typed(markup)) = markup.clone().filter(|_| overflow.is_empty()) {
+                msg_to_send = msg_to_send.reply_markup(markup);
             }
+
+            let sent = send_captioned(retry, msg_to_send, caption, "image message").await?;
+            send_caption_overflow(
+                ctx,
+                chat_id,
+                thread_id,
+                sent.id.0,
+                &overflow,
+                &data.options,
+                markup,
+            )
+            .await?
         }
 
         OutgoingMessageType::AudioMessage(data) => {
-            tracing::info!(audio_path = %data.audio_path, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending audio message to Telegram");
+            tracing::info!(audio = %data.audio, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending audio message to Telegram");
 
-            if !Path::new(&data.audio_path).exists() {
-                return Err(format!("Audio file not found: {}", data.audio_path).into());
-            }
-
-            let input_file = InputFile::file(&data.audio_path);
-            let mut msg_to_send = bot.send_audio(chat_id, input_file);
-
-            if let Some(caption) = data.caption {
-                let formatted_caption = format_telegram_markdown(&caption);
-                msg_to_send = msg_to_send.caption(formatted_caption);
-            }
+            let input_file = media::input_file(&data.audio, "Audio")?;
+            let mut msg_to_send = prepare(
+                bot.send_audio(chat_id, input_file),
+                thread_id,
+                &data.options,
+            );
 
+            let (caption, overflow) = caption_parts(&data.caption);
             if let Some(duration) = data.duration {
                 msg_to_send = msg_to_send.duration(duration);
             }
@@ -200,62 +544,70 @@ async fn handle_outgoing_message(
                 msg_to_send = msg_to_send.title(title);
             }
 
-            if let Some(markup) = create_markup(&data.buttons) {
+            // Buttons go on the last message
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            if let Some(Markup::Typed(markup)) = markup.clone().filter(|_| overflow.is_empty()) {
                 msg_to_send = msg_to_send.reply_markup(markup);
             }
 
-            if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                msg_to_send = msg_to_send.reply_markup(reply_keyboard);
-            }
-
-            msg_to_send.await?;
+            let sent = send_captioned(retry, msg_to_send, caption, "audio message").await?;
+            send_caption_overflow(
+                ctx,
+                chat_id,
+                thread_id,
+                sent.id.0,
+                &overflow,
+                &data.options,
+                markup,
+            )
+            .await?
         }
 
         OutgoingMessageType::VoiceMessage(data) => {
-            tracing::info!(voice_path = %data.voice_path, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending voice message to Telegram");
+            tracing::info!(voice = %data.voice, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending voice message to Telegram");
 
-            if !Path::new(&data.voice_path).exists() {
-                return Err(format!("Voice file not found: {}", data.voice_path).into());
-            }
-
-            let input_file = InputFile::file(&data.voice_path);
-            let mut msg_to_send = bot.send_voice(chat_id, input_file);
-
-            if let Some(caption) = data.caption {
-                let formatted_caption = format_telegram_markdown(&caption);
-                msg_to_send = msg_to_send.caption(formatted_caption);
-            }
+            let input_file = media::input_file(&data.voice, "Voice")?;
+            let mut msg_to_send = prepare(
+                bot.send_voice(chat_id, input_file),
+                thread_id,
+                &data.options,
+            );
 
+            let (caption, overflow) = caption_parts(&data.caption);
             if let Some(duration) = data.duration {
                 msg_to_send = msg_to_send.duration(duration);
             }
 
-            if let Some(markup) = create_markup(&data.buttons) {
+            // Buttons go on the last message
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            if let Some(Markup::Typed(markup)) = markup.clone().filter(|_| overflow.is_empty()) {
                 msg_to_send = msg_to_send.reply_markup(markup);
             }
 
-            if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                msg_to_send = msg_to_send.reply_markup(reply_keyboard);
-            }
-
-            msg_to_send.await?;
+            let sent = send_captioned(retry, msg_to_send, caption, "voice message").await?;
+            send_caption_overflow(
+                ctx,
+                chat_id,
+                thread_id,
+                sent.id.0,
+                &overflow,
+                &data.options,
+                markup,
+            )
+            .await?
         }
 
         OutgoingMessageType::VideoMessage(data) => {
-            tracing::info!(video_path = %data.video_path, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending video message to Telegram");
-
-            if !Path::new(&data.video_path).exists() {
-                return Err(format!("Video file not found: {}", data.video_path).into());
-            }
+            tracing::info!(video = %data.video, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending video message to Telegram");
 
-            let input_file = InputFile::file(&data.video_path);
-            let mut msg_to_send = bot.send_video(chat_id, input_file);
-
-            if let Some(caption) = data.caption {
-                let formatted_caption = format_telegram_markdown(&caption);
-                msg_to_send = msg_to_send.caption(formatted_caption);
-            }
+            let input_file = media::input_file(&data.video, "Video")?;
+            let mut msg_to_send = prepare(
+                bot.send_video(chat_id, input_file),
+                thread_id,
+                &data.options,
+            );
 
+            let (caption, overflow) = caption_parts(&data.caption);
             if let Some(duration) = data.duration {
                 msg_to_send = msg_to_send.duration(duration);
             }
@@ -272,26 +624,34 @@ async fn handle_outgoing_message(
                 msg_to_send = msg_to_send.supports_streaming(supports_streaming);
             }
 
-            if let Some(markup) = create_markup(&data.buttons) {
+            // Buttons go on the last message
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            if let Some(Markup::Typed(markup)) = markup.clone().filter(|_| overflow.is_empty()) {
                 msg_to_send = msg_to_send.reply_markup(markup);
             }
 
-            if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                msg_to_send = msg_to_send.reply_markup(reply_keyboard);
-            }
-
-            msg_to_send.await?;
+            let sent = send_captioned(retry, msg_to_send, caption, "video message").await?;
+            send_caption_overflow(
+                ctx,
+                chat_id,
+                thread_id,
+                sent.id.0,
+                &overflow,
+                &data.options,
+                markup,
+            )
+            .await?
         }
 
         OutgoingMessageType::VideoNoteMessage(data) => {
-            tracing::info!(video_note_path = %data.video_note_path, has_buttons = %data.buttons.is_some(), "Sending video note message to Telegram");
+            tracing::info!(video_note = %data.video_note, has_buttons = %data.buttons.is_some(), "Sending video note message to Telegram");
 
-            if !Path::new(&data.video_note_path).exists() {
-                return Err(format!("Video note file not found: {}", data.video_note_path).into());
-            }
-
-            let input_file = InputFile::file(&data.video_note_path);
-            let mut msg_to_send = bot.send_video_note(chat_id, input_file);
+            let input_file = media::input_file(&data.video_note, "Video note")?;
+            let mut msg_to_send = prepare(
+                bot.send_video_note(chat_id, input_file),
+                thread_id,
+                &data.options,
+            );
 
             if let Some(duration) = data.duration {
                 msg_to_send = msg_to_send.duration(duration);
@@ -301,171 +661,893 @@ async fn handle_outgoing_message(
                 msg_to_send = msg_to_send.length(length);
             }
 
-            if let Some(markup) = create_markup(&data.buttons) {
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            if let Some(Markup::Typed(markup)) = markup.clone() {
                 msg_to_send = msg_to_send.reply_markup(markup);
             }
 
-            if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                msg_to_send = msg_to_send.reply_markup(reply_keyboard);
+            let sent = retry.send(&msg_to_send).await?.id.0;
+            add_raw_markup(ctx, chat_id, sent, markup).await?;
+            vec![sent]
+        }
+
+        OutgoingMessageType::StickerMessage(data) => {
+            tracing::info!(sticker = %data.sticker, has_buttons = %data.buttons.is_some(), "Sending sticker message to Telegram");
+
+            let input_file = media::input_file(&data.sticker, "Sticker")?;
+            let mut msg_to_send = prepare(
+                bot.send_sticker(chat_id, input_file),
+                thread_id,
+                &data.options,
+            );
+
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            if let Some(Markup::Typed(markup)) = markup.clone() {
+                msg_to_send = msg_to_send.reply_markup(markup);
             }
 
-            msg_to_send.await?;
+            let sent = retry.send(&msg_to_send).await?.id.0;
+            add_raw_markup(ctx, chat_id, sent, markup).await?;
+            vec![sent]
         }
 
-        OutgoingMessageType::StickerMessage(data) => {
-            tracing::info!(sticker_path = %data.sticker_path, has_buttons = %data.buttons.is_some(), "Sending sticker message to Telegram");
+        OutgoingMessageType::AnimationMessage(data) => {
+            tracing::info!(animation = %data.animation, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending animation message to Telegram");
 
-            if !Path::new(&data.sticker_path).exists() {
-                return Err(format!("Sticker file not found: {}", data.sticker_path).into());
+            let input_file = media::input_file(&data.animation, "Animation")?;
+            let mut msg_to_send = prepare(
+                bot.send_animation(chat_id, input_file),
+                thread_id,
+                &data.options,
+            );
+
+            let (caption, overflow) = caption_parts(&data.caption);
+            if let Some(duration) = data.duration {
+                msg_to_send = msg_to_send.duration(duration);
             }
 
-            let input_file = InputFile::file(&data.sticker_path);
-            let mut msg_to_send = bot.send_sticker(chat_id, input_file);
+            if let Some(width) = data.width {
+                msg_to_send = msg_to_send.width(width);
+            }
 
-            if let Some(markup) = create_markup(&data.buttons) {
+            if let Some(height) = data.height {
+                msg_to_send = msg_to_send.height(height);
+            }
+
+            // Buttons go on the last message
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            if let Some(Markup::Typed(markup)) = markup.clone().filter(|_| overflow.is_empty()) {
                 msg_to_send = msg_to_send.reply_markup(markup);
             }
 
-            if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                msg_to_send = msg_to_send.reply_markup(reply_keyboard);
+            let sent = send_captioned(retry, msg_to_send, caption, "animation message").await?;
+            send_caption_overflow(
+                ctx,
+                chat_id,
+                thread_id,
+                sent.id.0,
+                &overflow,
+                &data.options,
+                markup,
+            )
+            .await?
+        }
+
+        OutgoingMessageType::DocumentMessage(data) => {
+            tracing::info!(document = %data.document, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending document message to Telegram");
+
+            let mut input_file = media::input_file(&data.document, "Document")?;
+            if let Some(filename) = &data.filename {
+                input_file = input_file.file_name(filename.clone());
+            }
+
+            let mut msg_to_send = prepare(
+                bot.send_document(chat_id, input_file),
+                thread_id,
+                &data.options,
+            );
+
+            let (caption, overflow) = caption_parts(&data.caption);
+            // Buttons go on the last message
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            if let Some(Markup::Typed(markup)) = markup.clone().filter(|_| overflow.is_empty()) {
+                msg_to_send = msg_to_send.reply_markup(markup);
             }
 
-            msg_to_send.await?;
+            let sent = send_captioned(retry, msg_to_send, caption, "document message").await?;
+            send_caption_overflow(
+                ctx,
+                chat_id,
+                thread_id,
+                sent.id.0,
+                &overflow,
+                &data.options,
+                markup,
+            )
+            .await?
         }
 
-        OutgoingMessageType::AnimationMessage(data) => {
-            tracing::info!(animation_path = %data.animation_path, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending animation message to Telegram");
+        OutgoingMessageType::MediaGroup(data) => {
+            tracing::info!(items = %data.media.len(), "Sending media group to Telegram");
+
+            data.validate()?;
+            let files = data
+                .media
+                .iter()
+                .map(|item| media::input_file(&item.media, "Media group"))
+                .collect::<Result<Vec<_>, _>>()?;
 
-            if !Path::new(&data.animation_path).exists() {
-                return Err(format!("Animation file not found: {}", data.animation_path).into());
+            let msg_to_send = prepare(
+                bot.send_media_group(chat_id, media_group(&data.media, &files, true)),
+                thread_id,
+                &data.options,
+            );
+            let sent = if data.media.iter().any(|item| item.parse_mode.is_some()) {
+                try_send_with_fallback(
+                    retry.send(&msg_to_send).await,
+                    || async {
+                        let plain_msg = prepare(
+                            bot.send_media_group(chat_id, media_group(&data.media, &files, false)),
+                            thread_id,
+                            &data.options,
+                        );
+                        retry.send(&plain_msg).await
+                    },
+                    "media group",
+                )
+                .await?
+            } else {
+                retry.send(&msg_to_send).await?
+            };
+            sent.iter().map(|message| message.id.0).collect()
+        }
+
+        OutgoingMessageType::LocationMessage(data) => {
+            tracing::info!(live_period = ?data.live_period, has_buttons = %data.buttons.is_some(), "Sending location to Telegram");
+
+            let mut msg_to_send = prepare(
+                bot.send_location(chat_id, data.latitude, data.longitude),
+                thread_id,
+                &data.options,
+            );
+
+            if let Some(horizontal_accuracy) = data.horizontal_accuracy {
+                msg_to_send = msg_to_send.horizontal_accuracy(horizontal_accuracy);
             }
 
-            let input_file = InputFile::file(&data.animation_path);
-            let mut msg_to_send = bot.send_animation(chat_id, input_file);
+            if let Some(live_period) = data.live_period {
+                msg_to_send = msg_to_send.live_period(LivePeriod::from_u32(live_period));
+            }
 
-            if let Some(caption) = data.caption {
-                let formatted_caption = format_telegram_markdown(&caption);
-                msg_to_send = msg_to_send.caption(formatted_caption);
+            if let Some(heading) = data.heading {
+                msg_to_send = msg_to_send.heading(heading);
             }
 
-            if let Some(duration) = data.duration {
-                msg_to_send = msg_to_send.duration(duration);
+            if let Some(radius) = data.proximity_alert_radius {
+                msg_to_send = msg_to_send.proximity_alert_radius(radius);
             }
 
-            if let Some(width) = data.width {
-                msg_to_send = msg_to_send.width(width);
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            vec![send_with_markup(ctx, msg_to_send, markup).await?.id.0]
+        }
+
+        OutgoingMessageType::VenueMessage(data) => {
+            tracing::info!(title = %data.title, has_buttons = %data.buttons.is_some(), "Sending venue to Telegram");
+
+            let mut msg_to_send = prepare(
+                bot.send_venue(
+                    chat_id,
+                    data.latitude,
+                    data.longitude,
+                    data.title,
+                    data.address,
+                ),
+                thread_id,
+                &data.options,
+            );
+
+            if let Some(foursquare_id) = data.foursquare_id {
+                msg_to_send = msg_to_send.foursquare_id(foursquare_id);
             }
 
-            if let Some(height) = data.height {
-                msg_to_send = msg_to_send.height(height);
+            if let Some(foursquare_type) = data.foursquare_type {
+                msg_to_send = msg_to_send.foursquare_type(foursquare_type);
             }
 
-            if let Some(markup) = create_markup(&data.buttons) {
-                msg_to_send = msg_to_send.reply_markup(markup);
+            if let Some(google_place_id) = data.google_place_id {
+                msg_to_send = msg_to_send.google_place_id(google_place_id);
             }
 
-            if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                msg_to_send = msg_to_send.reply_markup(reply_keyboard);
+            if let Some(google_place_type) = data.google_place_type {
+                msg_to_send = msg_to_send.google_place_type(google_place_type);
             }
 
-            msg_to_send.await?;
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            vec![send_with_markup(ctx, msg_to_send, markup).await?.id.0]
         }
 
-        OutgoingMessageType::DocumentMessage(data) => {
-            tracing::info!(document_path = %data.document_path, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Sending document message to Telegram");
+        OutgoingMessageType::ContactMessage(data) => {
+            tracing::info!(has_buttons = %data.buttons.is_some(), "Sending contact to Telegram");
+
+            let mut msg_to_send = prepare(
+                bot.send_contact(chat_id, data.phone_number, data.first_name),
+                thread_id,
+                &data.options,
+            );
 
-            if !Path::new(&data.document_path).exists() {
-                return Err(format!("Document file not found: {}", data.document_path).into());
+            if let Some(last_name) = data.last_name {
+                msg_to_send = msg_to_send.last_name(last_name);
             }
 
-            let input_file = if let Some(filename) = &data.filename {
-                InputFile::file(&data.document_path).file_name(filename.clone())
-            } else {
-                InputFile::file(&data.document_path)
+            if let Some(vcard) = data.vcard {
+                msg_to_send = msg_to_send.vcard(vcard);
+            }
+
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            vec![send_with_markup(ctx, msg_to_send, markup).await?.id.0]
+        }
+
+        OutgoingMessageType::DiceMessage(data) => {
+            tracing::info!(emoji = ?data.emoji, "Sending dice to Telegram");
+
+            let mut msg_to_send = prepare(bot.send_dice(chat_id), thread_id, &data.options);
+
+            if let Some(emoji) = &data.emoji {
+                let emoji: DiceEmoji =
+                    serde_json::from_value(serde_json::Value::from(emoji.as_str()))
+                        .map_err(|_| format!("Unsupported dice emoji: {emoji}"))?;
+                msg_to_send = msg_to_send.emoji(emoji);
+            }
+
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            vec![send_with_markup(ctx, msg_to_send, markup).await?.id.0]
+        }
+
+        OutgoingMessageType::PollMessage(data) => {
+            tracing::info!(options = %data.options.len(), poll_type = ?data.poll_type, "Sending poll to Telegram");
+
+            let poll_type = match data.poll_type.as_deref() {
+                None | Some("regular") => PollType::Regular,
+                Some("quiz") => PollType::Quiz,
+                Some(other) => return Err(format!("Unsupported poll type: {other}").into()),
             };
+            if poll_type == PollType::Quiz && data.correct_option_id.is_none() {
+                return Err("Quiz polls need a correct_option_id".into());
+            }
+
+            let mut msg_to_send = prepare(
+                bot.send_poll(
+                    chat_id,
+                    data.question,
+                    data.options.into_iter().map(Into::into),
+                ),
+                thread_id,
+                &data.send_options,
+            )
+            .type_(poll_type);
+
+            if let Some(is_anonymous) = data.is_anonymous {
+                msg_to_send = msg_to_send.is_anonymous(is_anonymous);
+            }
 
-            let mut msg_to_send = bot.send_document(chat_id, input_file);
+            if let Some(allows_multiple_answers) = data.allows_multiple_answers {
+                msg_to_send = msg_to_send.allows_multiple_answers(allows_multiple_answers);
+            }
 
-            if let Some(caption) = data.caption {
-                let formatted_caption = format_telegram_markdown(&caption);
-                msg_to_send = msg_to_send.caption(formatted_caption);
+            if let Some(correct_option_id) = data.correct_option_id {
+                msg_to_send = msg_to_send.correct_option_id(correct_option_id);
             }
 
-            if let Some(markup) = create_markup(&data.buttons) {
-                msg_to_send = msg_to_send.reply_markup(markup);
+            if let Some(explanation) = data.explanation {
+                msg_to_send = match data.explanation_parse_mode.as_deref() {
+                    Some("HTML") | Some("Markdown") => msg_to_send
+                        .explanation(format_telegram_markdown(&explanation))
+                        .explanation_parse_mode(ParseMode::Html),
+                    _ => msg_to_send.explanation(explanation),
+                };
             }
 
-            if let Some(reply_keyboard) = create_reply_keyboard(&data.reply_keyboard) {
-                msg_to_send = msg_to_send.reply_markup(reply_keyboard);
+            if let Some(open_period) = data.open_period {
+                msg_to_send = msg_to_send.open_period(open_period);
             }
 
-            msg_to_send.await?;
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            let sent = send_with_markup(ctx, msg_to_send, markup).await?;
+            poll_id = sent.poll().map(|poll| poll.id.clone());
+            vec![sent.id.0]
+        }
+
+        OutgoingMessageType::StopPoll(data) => {
+            tracing::info!(message_id = %data.message_id, "Stopping poll in Telegram");
+
+            let stop = bot.stop_poll(chat_id, teloxide::types::MessageId(data.message_id));
+            let poll = send_with_markup(ctx, stop, create_markup(&data.buttons)).await?;
+            tracing::info!(poll_id = %poll.id, total_voter_count = poll.total_voter_count, "Stopped poll");
+            vec![data.message_id]
+        }
+
+        OutgoingMessageType::StreamMessage(data) => {
+            tracing::info!(stream_id = %data.stream_id, action = ?data.action, text_length = data.text.as_deref().map_or(0, str::len), "Streaming message to Telegram");
+            ctx.streams.handle(ctx, chat_id, thread_id, data).await?
         }
 
         OutgoingMessageType::EditMessage(data) => {
             tracing::info!(message_id = %data.message_id, has_new_text = %data.new_text.is_some(), has_new_buttons = %data.new_buttons.is_some(), "Editing message in Telegram");
+            let message_id = teloxide::types::MessageId(data.message_id);
 
             if let Some(new_text) = data.new_text {
-                let formatted_text = format_telegram_markdown(&new_text);
-                let mut msg_to_edit = bot
-                    .edit_message_text(
-                        chat_id,
-                        teloxide::types::MessageId(data.message_id),
-                        formatted_text,
+                let edit = |text: String, parse_mode: Option<ParseMode>| {
+                    let mut edit = bot.edit_message_text(chat_id, message_id, text);
+                    if let Some(parse_mode) = parse_mode {
+                        edit = edit.parse_mode(parse_mode);
+                    }
+                    send_with_markup(ctx, edit, create_markup(&data.new_buttons))
+                };
+                let (text, parse_mode) = render_text(&new_text, &data.parse_mode);
+                let edited = if parse_mode.is_some() {
+                    try_send_with_fallback(
+                        edit(text.clone(), parse_mode).await,
+                        || edit(strip_html(&text), None),
+                        "edit message",
                     )
-                    .parse_mode(ParseMode::MarkdownV2);
+                    .await?
+                } else {
+                    edit(text, None).await?
+                };
+                vec![edited.id.0]
+            } else if let Some(markup) = create_markup(&data.new_buttons) {
+                // Edit only buttons if no new text is provided
+                let edit = bot.edit_message_reply_markup(chat_id, message_id);
+                let edited = send_with_markup(ctx, edit, Some(markup)).await?;
+                vec![edited.id.0]
+            } else {
+                Vec::new()
+            }
+        }
 
-                if let Some(markup) = create_markup(&data.new_buttons) {
-                    msg_to_edit = msg_to_edit.reply_markup(markup);
+        OutgoingMessageType::EditCaption(data) => {
+            tracing::info!(message_id = %data.message_id, has_caption = %data.caption.is_some(), has_buttons = %data.buttons.is_some(), "Editing caption in Telegram");
+            let message_id = teloxide::types::MessageId(data.message_id);
+
+            let edit = |caption: Option<String>, parse_mode: Option<ParseMode>| {
+                let mut edit = bot.edit_message_caption(chat_id, message_id);
+                if let Some(caption) = caption {
+                    edit = edit.caption(caption);
+                }
+                if let Some(parse_mode) = parse_mode {
+                    edit = edit.parse_mode(parse_mode);
                 }
+                send_with_markup(ctx, edit, create_markup(&data.buttons))
+            };
+            let (caption, parse_mode) = match &data.caption {
+                Some(caption) => {
+                    let (caption, parse_mode) = render_text(caption, &data.parse_mode);
+                    (Some(caption), parse_mode)
+                }
+                None => (None, None),
+            };
+            let edited = if parse_mode.is_some() {
+                try_send_with_fallback(
+                    edit(caption.clone(), parse_mode).await,
+                    || edit(caption.as_deref().map(strip_html), None),
+                    "edit caption",
+                )
+                .await?
+            } else {
+                edit(caption, None).await?
+            };
+            vec![edited.id.0]
+        }
 
+        OutgoingMessageType::EditMedia(data) => {
+            tracing::info!(message_id = %data.message_id, media = %data.media.media, kind = ?data.media.kind, "Editing media in Telegram");
+            let message_id = teloxide::types::MessageId(data.message_id);
+
+            let files = [media::input_file(&data.media.media, "Media")?];
+            let items = std::slice::from_ref(&data.media);
+            let markup = create_markup(&data.buttons);
+            let edit = |formatted: bool| {
+                let media = media_group(items, &files, formatted).remove(0);
+                let mut edit = bot.edit_message_media(chat_id, message_id, media);
+                if let Some(Markup::Typed(markup)) = markup.clone() {
+                    edit = edit.reply_markup(markup);
+                }
+                edit
+            };
+            let edited = if data.media.parse_mode.is_some() {
                 try_send_with_fallback(
-                    msg_to_edit.await,
-                    || async {
-                        let mut plain_edit = bot.edit_message_text(
-                            chat_id,
-                            teloxide::types::MessageId(data.message_id),
-                            &new_text,
-                        );
-                        if let Some(markup) = create_markup(&data.new_buttons) {
-                            plain_edit = plain_edit.reply_markup(markup);
-                        }
-                        plain_edit.await
-                    },
-                    "edit message",
+                    retry.send(&edit(true)).await,
+                    || async { retry.send(&edit(false)).await },
+                    "edit media",
                 )
+                .await?
+            } else {
+                retry.send(&edit(false)).await?
+            };
+            add_raw_markup(ctx, chat_id, edited.id.0, markup).await?;
+            vec![edited.id.0]
+        }
+
+        OutgoingMessageType::EditLiveLocation(data) => {
+            tracing::info!(message_id = %data.message_id, "Editing live location in Telegram");
+
+            let mut edit = bot.edit_message_live_location(
+                chat_id,
+                teloxide::types::MessageId(data.message_id),
+                data.latitude,
+                data.longitude,
+            );
+
+            if let Some(horizontal_accuracy) = data.horizontal_accuracy {
+                edit = edit.horizontal_accuracy(horizontal_accuracy);
+            }
+
+            if let Some(heading) = data.heading {
+                edit = edit.heading(heading);
+            }
+
+            if let Some(radius) = data.proximity_alert_radius {
+                edit = edit.proximity_alert_radius(radius);
+            }
+
+            let markup = create_markup(&data.buttons);
+            vec![send_with_markup(ctx, edit, markup).await?.id.0]
+        }
+
+        OutgoingMessageType::DeleteMessage(data) => {
+            tracing::info!(message_id = %data.message_id, "Deleting message in Telegram");
+            retry
+                .send(&bot.delete_message(chat_id, teloxide::types::MessageId(data.message_id)))
                 .await?;
-            } else if data.new_buttons.is_some() {
-                // Edit only buttons if no new text is provided
-                if let Some(markup) = create_markup(&data.new_buttons) {
-                    bot.edit_message_reply_markup(
+            vec![data.message_id]
+        }
+
+        OutgoingMessageType::ForwardMessage(data) => {
+            tracing::info!(from_chat_id = %data.from_chat_id, message_id = %data.message_id, "Forwarding message in Telegram");
+
+            let mut forward = in_thread(
+                bot.forward_message(
+                    chat_id,
+                    ChatId(data.from_chat_id),
+                    teloxide::types::MessageId(data.message_id),
+                ),
+                thread_id,
+            );
+            if let Some(disable_notification) = data.disable_notification {
+                forward = forward.disable_notification(disable_notification);
+            }
+            if let Some(protect_content) = data.protect_content {
+                forward = forward.protect_content(protect_content);
+            }
+            vec![retry.send(&forward).await?.id.0]
+        }
+
+        OutgoingMessageType::CopyMessage(data) => {
+            tracing::info!(from_chat_id = %data.from_chat_id, message_id = %data.message_id, has_caption = %data.caption.is_some(), "Copying message in Telegram");
+
+            if data.options.message_effect_id.is_some() {
+                return Err("message_effect_id cannot be used with CopyMessage".into());
+            }
+            let markup = reply_markup(&data.reply_markup, &data.buttons, &data.reply_keyboard)?;
+            let copy = |caption: Option<String>, parse_mode: Option<ParseMode>| {
+                let mut copy = prepare(
+                    bot.copy_message(
                         chat_id,
+                        ChatId(data.from_chat_id),
                         teloxide::types::MessageId(data.message_id),
-                    )
-                    .reply_markup(markup)
-                    .await?;
+                    ),
+                    thread_id,
+                    &data.options,
+                );
+                if let Some(caption) = caption {
+                    copy = copy.caption(caption);
+                }
+                if let Some(parse_mode) = parse_mode {
+                    copy = copy.parse_mode(parse_mode);
                 }
+                send_with_markup(ctx, copy, markup.clone())
+            };
+            let (caption, parse_mode) = match &data.caption {
+                Some(caption) => {
+                    let (caption, parse_mode) = render_text(caption, &data.parse_mode);
+                    (Some(caption), parse_mode)
+                }
+                None => (None, None),
+            };
+            let copied = if parse_mode.is_some() {
+                try_send_with_fallback(
+                    copy(caption.clone(), parse_mode).await,
+                    || copy(caption.as_deref().map(strip_html), None),
+                    "copied message",
+                )
+                .await?
+            } else {
+                copy(caption, None).await?
+            };
+            vec![copied.0]
+        }
+
+        OutgoingMessageType::CopyMessages(data) => {
+            tracing::info!(from_chat_id = %data.from_chat_id, messages = data.message_ids.len(), "Copying messages in Telegram");
+
+            let mut copy = in_thread(
+                bot.copy_messages(
+                    chat_id,
+                    ChatId(data.from_chat_id),
+                    data.message_ids
+                        .iter()
+                        .map(|&id| teloxide::types::MessageId(id)),
+                ),
+                thread_id,
+            );
+            if let Some(remove_caption) = data.remove_caption {
+                copy = copy.remove_caption(remove_caption);
+            }
+            if let Some(disable_notification) = data.disable_notification {
+                copy = copy.disable_notification(disable_notification);
             }
+            if let Some(protect_content) = data.protect_content {
+                copy = copy.protect_content(protect_content);
+            }
+            let copied = retry.send(&copy).await?;
+            copied.iter().map(|id| id.0).collect()
         }
 
-        OutgoingMessageType::DeleteMessage(data) => {
-            tracing::info!(message_id = %data.message_id, "Deleting message in Telegram");
-            bot.delete_message(chat_id, teloxide::types::MessageId(data.message_id))
+        OutgoingMessageType::PinChatMessage(data) => {
+            tracing::info!(message_id = %data.message_id, "Pinning message in Telegram");
+
+            let mut pin =
+                bot.pin_chat_message(chat_id, teloxide::types::MessageId(data.message_id));
+            if let Some(disable_notification) = data.disable_notification {
+                pin = pin.disable_notification(disable_notification);
+            }
+            retry.send(&pin).await?;
+            vec![data.message_id]
+        }
+
+        OutgoingMessageType::UnpinChatMessage(data) => {
+            tracing::info!(message_id = ?data.message_id, "Unpinning message in Telegram");
+
+            let mut unpin = bot.unpin_chat_message(chat_id);
+            if let Some(message_id) = data.message_id {
+                unpin = unpin.message_id(teloxide::types::MessageId(message_id));
+            }
+            retry.send(&unpin).await?;
+            data.message_id.into_iter().collect()
+        }
+
+        OutgoingMessageType::UnpinAllChatMessages(_data) => {
+            tracing::info!("Unpinning all messages in Telegram");
+            retry.send(&bot.unpin_all_chat_messages(chat_id)).await?;
+            Vec::new()
+        }
+
+        OutgoingMessageType::SetReaction(data) => {
+            tracing::info!(message_id = %data.message_id, reaction = ?data.reaction, "Setting reaction in Telegram");
+
+            let reaction: Vec<ReactionType> = data
+                .reaction
+                .iter()
+                .map(|r| reaction_from_string(r))
+                .collect();
+            if reaction.contains(&ReactionType::Paid) {
+                return Err("Bots cannot set paid reactions".into());
+            }
+            let mut react = bot
+                .set_message_reaction(chat_id, teloxide::types::MessageId(data.message_id))
+                .reaction(reaction);
+            if let Some(is_big) = data.is_big {
+                react = react.is_big(is_big);
+            }
+            retry.send(&react).await?;
+            vec![data.message_id]
+        }
+
+        OutgoingMessageType::BanChatMember(data) => {
+            tracing::info!(user_id = %data.user_id, until_date = ?data.until_date, "Banning chat member in Telegram");
+
+            let mut ban = bot.ban_chat_member(chat_id, UserId(data.user_id));
+            if let Some(until_date) = data.until_date {
+                ban = ban.until_date(until_date);
+            }
+            if let Some(revoke_messages) = data.revoke_messages {
+                ban = ban.revoke_messages(revoke_messages);
+            }
+            retry.send(&ban).await?;
+            Vec::new()
+        }
+
+        OutgoingMessageType::UnbanChatMember(data) => {
+            tracing::info!(user_id = %data.user_id, "Unbanning chat member in Telegram");
+
+            let mut unban = bot.unban_chat_member(chat_id, UserId(data.user_id));
+            if let Some(only_if_banned) = data.only_if_banned {
+                unban = unban.only_if_banned(only_if_banned);
+            }
+            retry.send(&unban).await?;
+            Vec::new()
+        }
+
+        OutgoingMessageType::RestrictChatMember(data) => {
+            tracing::info!(user_id = %data.user_id, until_date = ?data.until_date, "Restricting chat member in Telegram");
+
+            let mut restrict = bot.restrict_chat_member(
+                chat_id,
+                UserId(data.user_id),
+                create_chat_permissions(&data.permissions),
+            );
+            if let Some(until_date) = data.until_date {
+                restrict = restrict.until_date(until_date);
+            }
+            if let Some(independent) = data.use_independent_chat_permissions {
+                restrict = restrict.use_independent_chat_permissions(independent);
+            }
+            retry.send(&restrict).await?;
+            Vec::new()
+        }
+
+        OutgoingMessageType::PromoteChatMember(data) => {
+            tracing::info!(user_id = %data.user_id, "Promoting chat member in Telegram");
+
+            let mut promote = bot.promote_chat_member(chat_id, UserId(data.user_id));
+            let rights = promote.payload_mut();
+            rights.is_anonymous = data.is_anonymous;
+            rights.can_manage_chat = data.can_manage_chat;
+            rights.can_delete_messages = data.can_delete_messages;
+            rights.can_manage_video_chats = data.can_manage_video_chats;
+            rights.can_restrict_members = data.can_restrict_members;
+            rights.can_promote_members = data.can_promote_members;
+            rights.can_change_info = data.can_change_info;
+            rights.can_invite_users = data.can_invite_users;
+            rights.can_post_messages = data.can_post_messages;
+            rights.can_edit_messages = data.can_edit_messages;
+            rights.can_pin_messages = data.can_pin_messages;
+            rights.can_post_stories = data.can_post_stories;
+            rights.can_edit_stories = data.can_edit_stories;
+            rights.can_delete_stories = data.can_delete_stories;
+            rights.can_manage_topics = data.can_manage_topics;
+            retry.send(&promote).await?;
+            Vec::new()
+        }
+
+        OutgoingMessageType::ApproveChatJoinRequest(data) => {
+            tracing::info!(user_id = %data.user_id, "Approving chat join request in Telegram");
+            retry
+                .send(&bot.approve_chat_join_request(chat_id, UserId(data.user_id)))
                 .await?;
+            Vec::new()
+        }
+
+        OutgoingMessageType::DeclineChatJoinRequest(data) => {
+            tracing::info!(user_id = %data.user_id, "Declining chat join request in Telegram");
+            retry
+                .send(&bot.decline_chat_join_request(chat_id, UserId(data.user_id)))
+                .await?;
+            Vec::new()
+        }
+
+        OutgoingMessageType::AnswerCallbackQuery(data) => {
+            tracing::info!(callback_query_id = %data.callback_query_id, show_alert = ?data.show_alert, "Answering callback query");
+
+            if !ctx.callback_answers.claim(&data.callback_query_id) {
+                tracing::warn!(callback_query_id = %data.callback_query_id, "Callback query was already answered, dropping the backend's answer");
+                return Ok(Handled {
+                    message_ids: Vec::new(),
+                    poll_id: None,
+                });
+            }
+            let mut answer = bot.answer_callback_query(data.callback_query_id);
+            if let Some(text) = data.text {
+                answer = answer.text(text);
+            }
+            if let Some(show_alert) = data.show_alert {
+                answer = answer.show_alert(show_alert);
+            }
+            if let Some(url) = data.url {
+                answer = answer.url(url);
+            }
+            if let Some(cache_time) = data.cache_time {
+                answer = answer.cache_time(cache_time);
+            }
+            retry.send(&answer).await?;
+            Vec::new()
         }
 
-        OutgoingMessageType::TypingMessage(_data) => {
-            tracing::info!("Sending typing action to Telegram");
-            bot.send_chat_action(chat_id, teloxide::types::ChatAction::Typing)
+        OutgoingMessageType::TypingMessage(data) => {
+            let action = data.action.unwrap_or_default();
+            let until_reply = data.until_reply.unwrap_or(false);
+            tracing::info!(?action, until_reply, "Sending chat action to Telegram");
+            retry
+                .send(&in_thread(send_chat_action(bot, chat_id, action), thread_id))
                 .await?;
+            let lane = LaneKey {
+                chat_id: chat_id.0,
+                thread_id,
+            };
+            if until_reply {
+                let timeout = data.timeout_secs.unwrap_or(DEFAULT_TIMEOUT_SECS);
+                ctx.chat_actions
+                    .keep_alive(bot, lane, action, Duration::from_secs(timeout));
+            } else {
+                ctx.chat_actions.stop(lane);
+            }
+            Vec::new()
         }
+    };
+
+    Ok(Handled {
+        message_ids,
+        poll_id,
+    })
+}
+
+/// How often a dead letter is published before its delivery is nacked.
+const DEAD_LETTER_ATTEMPTS: u32 = 5;
+
+/// Delay between attempts to publish a dead letter.
+const DEAD_LETTER_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Ack a delivery once it was handled. A failed delivery is acked only after it
+/// was stored in the dead-letter queue, and nacked for redelivery otherwise.
+///
+/// Storing the dead letter is retried in place, so the lane stays held and
+/// later messages for the chat keep their order.
+async fn settle(broker: &dyn MessageBroker, delivery: Delivery, failure: Option<DeadLetterRecord>) {
+    let settled = match failure {
+        None => delivery.ack().await,
+        Some(record) => {
+            let mut attempt = 1;
+            loop {
+                match publish_dead_letter(broker, &record).await {
+                    Ok(()) => break delivery.ack().await,
+                    Err(e) if attempt < DEAD_LETTER_ATTEMPTS => {
+                        tracing::warn!(error = %e, trace_id = ?record.trace_id, attempt, delay = ?DEAD_LETTER_RETRY_DELAY, "Failed to publish dead letter, retrying");
+                        tokio::time::sleep(DEAD_LETTER_RETRY_DELAY).await;
+                        attempt += 1;
+                    }
+                    Err(e) => {
+                        tracing::error!(error = %e, trace_id = ?record.trace_id, attempts = attempt, "Failed to publish dead letter, giving up");
+                        break delivery.nack().await;
+                    }
+                }
+            }
+        }
+    };
+    if let Err(e) = settled {
+        tracing::warn!(error = %e, "Failed to settle broker delivery");
     }
+}
 
+async fn publish_dead_letter(
+    broker: &dyn MessageBroker,
+    record: &DeadLetterRecord,
+) -> anyhow::Result<()> {
+    let payload = serde_json::to_vec(record)?;
+    let key = record.chat_id.map(|chat_id| chat_id.to_string());
+    broker.publish_dead_letter(key.as_deref(), &payload).await?;
+    tracing::warn!(
+        trace_id = ?record.trace_id,
+        chat_id = ?record.chat_id,
+        error_kind = ?record.error_kind,
+        "Published outgoing message to dead-letter queue"
+    );
     Ok(())
 }
 
-pub async fn start_broker_consumer_loop(bot_consumer_clone: Bot, broker: Arc<dyn MessageBroker>) {
-    tracing::info!("Starting broker consumer stream for Telegram output...");
+/// Publish a delivery receipt. Receipts are informational, so failures are
+/// only logged and never hold back the delivery itself.
+async fn publish_receipt(broker: &dyn MessageBroker, receipt: &DeliveryReceipt) {
+    let key = receipt.chat_id.to_string();
+    let result = match serde_json::to_vec(receipt) {
+        Ok(payload) => broker.publish_receipt(Some(&key), &payload).await,
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(()) => {
+            tracing::debug!(status = ?receipt.status, message_ids = ?receipt.message_ids, "Published delivery receipt")
+        }
+        Err(e) => tracing::warn!(error = %e, "Failed to publish delivery receipt"),
+    }
+}
+
+fn outgoing_span(out_msg: &OutgoingMessage) -> tracing::Span {
+    tracing::info_span!(
+        "handle_outgoing_message",
+        trace_id = %out_msg.trace_id,
+        chat_id = %out_msg.target.chat_id,
+        message_type = ?std::mem::discriminant(&out_msg.message_type)
+    )
+}
+
+/// Whether `message_type` waits for the rate limiter before it is handled.
+/// Chat actions and callback answers are not messages and would only go
+/// stale in the queue. Streams acquire a slot for each edit they actually make.
+fn is_rate_limited(message_type: &OutgoingMessageType) -> bool {
+    !matches!(
+        message_type,
+        OutgoingMessageType::TypingMessage(_)
+            | OutgoingMessageType::StreamMessage(_)
+            | OutgoingMessageType::AnswerCallbackQuery(_)
+    )
+}
+
+/// Send and settle a single outgoing message that passed the rate limiter.
+async fn deliver(
+    ctx: &OutgoingContext,
+    broker: &dyn MessageBroker,
+    out_msg: OutgoingMessage,
+    delivery: Delivery,
+) {
+    let span = outgoing_span(&out_msg);
+    let trace_id = out_msg.trace_id;
+    let chat_id = out_msg.target.chat_id;
+    // A reply ends the chat action kept alive while it was being prepared.
+    if !matches!(
+        out_msg.message_type,
+        OutgoingMessageType::TypingMessage(_) | OutgoingMessageType::AnswerCallbackQuery(_)
+    ) {
+        ctx.chat_actions.stop(LaneKey {
+            chat_id,
+            thread_id: out_msg.target.thread_id,
+        });
+    }
+    // A panic is dead-lettered like any other failure, so the delivery is settled.
+    let handled = AssertUnwindSafe(handle_outgoing_message(ctx, out_msg))
+        .catch_unwind()
+        .instrument(span.clone())
+        .await;
+    let (receipt, failure) = match handled {
+        Ok(Ok(handled)) => (
+            DeliveryReceipt::delivered(trace_id, chat_id, handled.message_ids)
+                .with_poll_id(handled.poll_id),
+            None,
+        ),
+        Ok(Err(e)) => {
+            tracing::error!(parent: &span, error = ?e, "Error handling OutgoingMessage");
+            // Parts of a split message that were sent before the failure.
+            let sent = e
+                .downcast_ref::<PartialSend>()
+                .map(|e| e.message_ids.clone())
+                .unwrap_or_default();
+            (
+                DeliveryReceipt::failed(trace_id, chat_id, e.to_string())
+                    .with_message_ids(sent.clone()),
+                Some(
+                    DeadLetterRecord::new(
+                        &delivery.payload,
+                        ErrorKind::classify(e.as_ref()),
+                        e.to_string(),
+                    )
+                    .with_attempts(RetryError::attempts_of(e.as_ref()))
+                    .with_sent_message_ids(sent),
+                ),
+            )
+        }
+        Err(_) => {
+            let error = "Outgoing message handler panicked".to_string();
+            tracing::error!(parent: &span, "{error}");
+            (
+                DeliveryReceipt::failed(trace_id, chat_id, error.clone()),
+                Some(DeadLetterRecord::new(
+                    &delivery.payload,
+                    ErrorKind::Panicked,
+                    error,
+                )),
+            )
+        }
+    };
+    publish_receipt(broker, &receipt)
+        .instrument(span.clone())
+        .await;
+    settle(broker, delivery, failure).instrument(span).await;
+}
+
+pub async fn start_broker_consumer_loop(ctx: OutgoingContext, broker: Arc<dyn MessageBroker>) {
+    tracing::info!(
+        concurrency = ctx.dispatch.concurrency,
+        "Starting broker consumer stream for Telegram output..."
+    );
+    ctx.rate_limiter.spawn_metrics_reporter(broker.clone());
     let mut stream = match broker.subscribe().await {
         Ok(s) => s,
         Err(e) => {
@@ -473,37 +1555,56 @@ pub async fn start_broker_consumer_loop(bot_consumer_clone: Bot, broker: Arc<dyn
             return;
         }
     };
-    while let Some(payload) = stream.next().await {
-        match serde_json::from_slice::<OutgoingMessage>(&payload) {
-            Ok(out_msg) => {
-                if out_msg.trace_id.is_nil() {
-                    tracing::warn!(
-                        "Generated new trace ID for message without one: {}",
-                        out_msg.trace_id
-                    );
-                    // early return
-                    return;
-                }
-
-                let span = tracing::info_span!(
-                    "handle_outgoing_message",
-                    trace_id = %out_msg.trace_id,
-                    chat_id = %out_msg.target.chat_id,
-                    message_type = ?std::mem::discriminant(&out_msg.message_type)
-                );
-
-                if let Err(e) = handle_outgoing_message(&bot_consumer_clone, out_msg)
-                    .instrument(span)
-                    .await
-                {
-                    tracing::error!(error = ?e, "Error handling OutgoingMessage");
-                }
-            }
+    // Different chats are served in parallel; messages for the same chat and
+    // thread keep their order.
+    let dispatcher = Dispatcher::new(&ctx.dispatch);
+    while let Some(delivery) = stream.next().await {
+        let out_msg = match serde_json::from_slice::<OutgoingMessage>(&delivery.payload) {
+            Ok(out_msg) => out_msg,
             Err(e) => {
                 tracing::error!(error = %e, "Error deserializing message from broker payload");
-                tracing::debug!(raw_payload = ?String::from_utf8_lossy(&payload), "Problematic broker payload");
+                tracing::debug!(raw_payload = ?String::from_utf8_lossy(&delivery.payload), "Problematic broker payload");
+                let record = DeadLetterRecord::new(
+                    &delivery.payload,
+                    ErrorKind::MalformedPayload,
+                    e.to_string(),
+                );
+                settle(broker.as_ref(), delivery, Some(record)).await;
+                continue;
+            }
+        };
+        if out_msg.trace_id.is_nil() {
+            tracing::error!(chat_id = out_msg.target.chat_id, "Outgoing message has a nil trace ID");
+            let record = DeadLetterRecord::new(
+                &delivery.payload,
+                ErrorKind::MalformedPayload,
+                "trace_id must not be nil",
+            );
+            settle(broker.as_ref(), delivery, Some(record)).await;
+            continue;
+        }
+
+        let lane = LaneKey {
+            chat_id: out_msg.target.chat_id,
+            thread_id: out_msg.target.thread_id,
+        };
+        // Waiting for the rate limiter keeps the lane's order but not a worker,
+        // so a throttled chat does not hold up the others.
+        let rate_limiter = is_rate_limited(&out_msg.message_type).then(|| ctx.rate_limiter.clone());
+        let ready = async move {
+            if let Some(rate_limiter) = rate_limiter {
+                rate_limiter.acquire(lane.chat_id).await;
             }
         }
+        .instrument(outgoing_span(&out_msg));
+        let ctx = ctx.clone();
+        let broker = broker.clone();
+        dispatcher
+            .dispatch_when(lane, ready, async move {
+                deliver(&ctx, broker.as_ref(), out_msg, delivery).await;
+            })
+            .await;
     }
+    dispatcher.wait_idle().await;
     tracing::warn!("Broker consumer stream ended.");
 }
@@ -0,0 +1,293 @@
+//! Options shared by many Bot API payloads, applied uniformly through
+//! `HasPayload::payload_mut`.
+
+use super::chat_action::SendChatAction;
+use super::outgoing::SendOptions;
+use serde::{Serialize, Serializer};
+use teloxide::payloads::{
+    CopyMessage, CopyMessages, EditMessageCaption, EditMessageLiveLocation, EditMessageMedia,
+    EditMessageReplyMarkup, EditMessageText, ForwardMessage, SendAnimation, SendAudio, SendContact,
+    SendDice, SendDocument, SendLocation, SendMediaGroup, SendMessage, SendPhoto, SendPoll,
+    SendSticker, SendVenue, SendVideo, SendVideoNote, SendVoice, StopPoll,
+};
+use teloxide::requests::{HasPayload, Payload};
+use teloxide::types::{
+    InlineKeyboardMarkup, MessageId, ParseMode, ReplyMarkup, ReplyParameters, ThreadId,
+};
+
+/// Payloads that can be sent into a forum topic.
+pub(crate) trait ThreadedPayload {
+    fn set_message_thread_id(&mut self, thread_id: ThreadId);
+}
+
+macro_rules! impl_threaded_payload {
+    ($($payload:ty),* $(,)?) => {
+        $(
+            impl ThreadedPayload for $payload {
+                fn set_message_thread_id(&mut self, thread_id: ThreadId) {
+                    self.message_thread_id = Some(thread_id);
+                }
+            }
+        )*
+    };
+}
+
+impl_threaded_payload!(
+    SendMessage,
+    SendPhoto,
+    SendAudio,
+    SendVoice,
+    SendVideo,
+    SendVideoNote,
+    SendSticker,
+    SendAnimation,
+    SendDocument,
+    SendMediaGroup,
+    SendLocation,
+    SendVenue,
+    SendContact,
+    SendDice,
+    SendPoll,
+    SendChatAction,
+    ForwardMessage,
+    CopyMessage,
+    CopyMessages,
+);
+
+/// Payloads that send a new message.
+pub(crate) trait SendPayload: ThreadedPayload {
+    fn apply_options(&mut self, options: &SendOptions);
+}
+
+macro_rules! impl_send_payload {
+    ($($payload:ty),* $(,)?) => {
+        $(
+            impl SendPayload for $payload {
+                fn apply_options(&mut self, options: &SendOptions) {
+                    self.reply_parameters = reply_parameters(options);
+                    if options.disable_notification.is_some() {
+                        self.disable_notification = options.disable_notification;
+                    }
+                    if options.protect_content.is_some() {
+                        self.protect_content = options.protect_content;
+                    }
+                    if options.message_effect_id.is_some() {
+                        self.message_effect_id = options.message_effect_id.clone();
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_send_payload!(
+    SendMessage,
+    SendPhoto,
+    SendAudio,
+    SendVoice,
+    SendVideo,
+    SendVideoNote,
+    SendSticker,
+    SendAnimation,
+    SendDocument,
+    SendMediaGroup,
+    SendLocation,
+    SendVenue,
+    SendContact,
+    SendDice,
+    SendPoll,
+);
+
+// Copies cannot show a message effect.
+impl SendPayload for CopyMessage {
+    fn apply_options(&mut self, options: &SendOptions) {
+        self.reply_parameters = reply_parameters(options);
+        if options.disable_notification.is_some() {
+            self.disable_notification = options.disable_notification;
+        }
+        if options.protect_content.is_some() {
+            self.protect_content = options.protect_content;
+        }
+    }
+}
+
+/// Payloads with a caption.
+pub(crate) trait CaptionPayload {
+    /// Set `caption`, or clear the caption with `None`, and its parse mode.
+    fn set_caption(&mut self, caption: Option<String>, parse_mode: Option<ParseMode>);
+}
+
+macro_rules! impl_caption_payload {
+    ($($payload:ty),* $(,)?) => {
+        $(
+            impl CaptionPayload for $payload {
+                fn set_caption(&mut self, caption: Option<String>, parse_mode: Option<ParseMode>) {
+                    self.caption = caption;
+                    self.parse_mode = parse_mode;
+                }
+            }
+        )*
+    };
+}
+
+impl_caption_payload!(SendPhoto, SendAudio, SendVoice, SendVideo, SendAnimation, SendDocument);
+
+/// Payloads with reply markup of type `Markup`.
+pub(crate) trait MarkupPayload {
+    type Markup;
+
+    fn set_reply_markup(&mut self, markup: Self::Markup);
+}
+
+macro_rules! impl_markup_payload {
+    ($markup:ty: $($payload:ty),* $(,)?) => {
+        $(
+            impl MarkupPayload for $payload {
+                type Markup = $markup;
+
+                fn set_reply_markup(&mut self, markup: $markup) {
+                    self.reply_markup = Some(markup.into());
+                }
+            }
+        )*
+    };
+}
+
+impl_markup_payload!(
+    ReplyMarkup: SendMessage,
+    SendPhoto,
+    SendAudio,
+    SendVoice,
+    SendVideo,
+    SendVideoNote,
+    SendSticker,
+    SendAnimation,
+    SendDocument,
+    SendLocation,
+    SendVenue,
+    SendContact,
+    SendDice,
+    SendPoll,
+    CopyMessage,
+);
+
+impl_markup_payload!(
+    InlineKeyboardMarkup: EditMessageText,
+    EditMessageCaption,
+    EditMessageMedia,
+    EditMessageReplyMarkup,
+    EditMessageLiveLocation,
+    StopPoll,
+);
+
+/// `P` with `reply_markup` given as raw Bot API JSON, for inline keyboards
+/// teloxide's typed markup cannot express. It can only be sent as JSON, so
+/// not with uploads.
+#[derive(Debug, Clone)]
+pub(crate) struct RawMarkup<P> {
+    payload: P,
+    reply_markup: serde_json::Value,
+}
+
+impl<P> RawMarkup<P> {
+    pub(crate) fn new(payload: P, reply_markup: serde_json::Value) -> Self {
+        Self {
+            payload,
+            reply_markup,
+        }
+    }
+}
+
+impl<P: Payload> Payload for RawMarkup<P> {
+    type Output = P::Output;
+
+    const NAME: &'static str = P::NAME;
+}
+
+impl<P: Serialize> Serialize for RawMarkup<P> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut payload = serde_json::to_value(&self.payload).map_err(serde::ser::Error::custom)?;
+        payload["reply_markup"] = self.reply_markup.clone();
+        payload.serialize(serializer)
+    }
+}
+
+fn reply_parameters(options: &SendOptions) -> Option<ReplyParameters> {
+    let message_id = options.reply_to_message_id?;
+    let mut parameters = ReplyParameters::new(MessageId(message_id));
+    parameters.quote = options.quote.clone();
+    parameters.allow_sending_without_reply = options.allow_sending_without_reply;
+    Some(parameters)
+}
+
+/// Send `request` into the forum topic `thread_id`, if there is one, with the
+/// shared send options applied.
+pub(crate) fn prepare<R>(request: R, thread_id: Option<i32>, options: &SendOptions) -> R
+where
+    R: HasPayload,
+    R::Payload: SendPayload,
+{
+    let mut request = in_thread(request, thread_id);
+    request.payload_mut().apply_options(options);
+    request
+}
+
+/// Send `request` into the forum topic `thread_id`, if there is one.
+pub(crate) fn in_thread<R>(mut request: R, thread_id: Option<i32>) -> R
+where
+    R: HasPayload,
+    R::Payload: ThreadedPayload,
+{
+    if let Some(thread_id) = thread_id {
+        request
+            .payload_mut()
+            .set_message_thread_id(ThreadId(MessageId(thread_id)));
+    }
+    request
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kafka_processing::outgoing::TextMessageData;
+    use teloxide::prelude::{Bot, ChatId, Requester};
+
+    #[test]
+    fn send_options_become_reply_parameters_and_flags() {
+        let data: TextMessageData = serde_json::from_value(serde_json::json!({
+            "text": "answer",
+            "reply_to_message_id": 42,
+            "quote": "the question",
+            "allow_sending_without_reply": true,
+            "disable_notification": true
+        }))
+        .unwrap();
+
+        let bot = Bot::new("token");
+        let request = prepare(
+            bot.send_message(ChatId(1), "answer"),
+            Some(7),
+            &data.options,
+        );
+        let payload = request.payload_ref();
+
+        let reply = payload.reply_parameters.as_ref().unwrap();
+        assert_eq!(reply.message_id, MessageId(42));
+        assert_eq!(reply.quote.as_deref(), Some("the question"));
+        assert_eq!(reply.allow_sending_without_reply, Some(true));
+        assert_eq!(payload.disable_notification, Some(true));
+        assert_eq!(payload.protect_content, None);
+        assert_eq!(payload.message_thread_id, Some(ThreadId(MessageId(7))));
+    }
+
+    #[test]
+    fn quote_without_reply_target_is_ignored() {
+        let options = SendOptions {
+            quote: Some("orphan".to_string()),
+            ..Default::default()
+        };
+        let bot = Bot::new("token");
+        let request = prepare(bot.send_message(ChatId(1), "text"), None, &options);
+        assert!(request.payload_ref().reply_parameters.is_none());
+    }
+}
@@ -0,0 +1,352 @@
+use crate::broker::MessageBroker;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Per-chat buckets are dropped once they are full again, but only when there
+/// are more than this many of them.
+const PRUNE_THRESHOLD: usize = 1024;
+
+/// Outgoing rate limits (`[rate_limit]` section of the config file).
+///
+/// The defaults follow Telegram's documented limits: about one message per
+/// second in a chat, 20 messages per minute in a group and 30 messages per
+/// second overall.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RateLimitOptions {
+    pub enabled: bool,
+    pub chat_per_second: f64,
+    /// Messages a chat may receive back to back before the per-second rate applies
+    pub chat_burst: u32,
+    pub group_per_minute: f64,
+    pub group_burst: u32,
+    pub global_per_second: f64,
+    pub global_burst: u32,
+    /// How often the queue depth is published as metrics; 0 disables them
+    pub metrics_interval_secs: u64,
+}
+
+impl Default for RateLimitOptions {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            chat_per_second: 1.0,
+            chat_burst: 3,
+            group_per_minute: 20.0,
+            group_burst: 3,
+            global_per_second: 30.0,
+            global_burst: 30,
+            metrics_interval_secs: 60,
+        }
+    }
+}
+
+/// Published to the metrics topic (`{prefix}.metrics`) every
+/// `metrics_interval_secs`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RateLimitMetrics {
+    /// Outgoing messages waiting for the rate limiter
+    pub queue_depth: usize,
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(burst: u32, refill_per_sec: f64, now: Instant) -> Self {
+        let capacity = f64::from(burst.max(1));
+        Self {
+            capacity,
+            tokens: capacity,
+            refill_per_sec,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// Time until a token is available; zero if one is available now.
+    fn wait_time(&mut self, now: Instant) -> Duration {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            Duration::ZERO
+        } else if self.refill_per_sec <= 0.0 {
+            Duration::MAX
+        } else {
+            Duration::from_secs_f64((1.0 - self.tokens) / self.refill_per_sec)
+        }
+    }
+
+    fn take(&mut self) {
+        self.tokens -= 1.0;
+    }
+
+    fn is_full(&self) -> bool {
+        self.tokens >= self.capacity
+    }
+}
+
+#[derive(Debug)]
+struct Buckets {
+    global: TokenBucket,
+    chats: HashMap<i64, TokenBucket>,
+    groups: HashMap<i64, TokenBucket>,
+}
+
+/// Token-bucket scheduler for outgoing messages.
+///
+/// A message for a chat needs a token from the chat's bucket, from the group
+/// bucket when the chat is a group (negative chat ID), and from the global
+/// bucket. Callers that find a bucket empty wait until it refills, so messages
+/// are delayed rather than rejected.
+#[derive(Debug)]
+pub struct RateLimiter {
+    options: RateLimitOptions,
+    buckets: Mutex<Buckets>,
+    waiting: AtomicUsize,
+}
+
+impl RateLimiter {
+    pub fn new(options: RateLimitOptions) -> Self {
+        let global = TokenBucket::new(
+            options.global_burst,
+            options.global_per_second,
+            Instant::now(),
+        );
+        Self {
+            options,
+            buckets: Mutex::new(Buckets {
+                global,
+                chats: HashMap::new(),
+                groups: HashMap::new(),
+            }),
+            waiting: AtomicUsize::new(0),
+        }
+    }
+
+    /// Number of messages currently waiting for a token.
+    pub fn queue_depth(&self) -> usize {
+        self.waiting.load(Ordering::Relaxed)
+    }
+
+    /// Wait until a message may be sent to `chat_id`.
+    pub async fn acquire(&self, chat_id: i64) {
+        if !self.options.enabled {
+            return;
+        }
+
+        let mut queued = None;
+        loop {
+            let wait = self.try_acquire(chat_id, Instant::now());
+            if wait.is_zero() {
+                break;
+            }
+            if queued.is_none() {
+                let queue_depth = self.waiting.fetch_add(1, Ordering::Relaxed) + 1;
+                tracing::debug!(
+                    chat_id,
+                    queue_depth,
+                    wait_ms = wait.as_millis() as u64,
+                    "Rate limited, queueing outgoing message"
+                );
+                queued = Some(Instant::now());
+            }
+            tokio::time::sleep(wait).await;
+        }
+
+        if let Some(since) = queued {
+            let queue_depth = self.waiting.fetch_sub(1, Ordering::Relaxed) - 1;
+            tracing::debug!(
+                chat_id,
+                queue_depth,
+                waited_ms = since.elapsed().as_millis() as u64,
+                "Rate limit released outgoing message"
+            );
+        }
+    }
+
+    /// Take a token from every bucket `chat_id` needs if all of them have one;
+    /// otherwise take nothing and return how long to wait before trying again.
+    fn try_acquire(&self, chat_id: i64, now: Instant) -> Duration {
+        let options = &self.options;
+        let mut buckets = self.buckets.lock().unwrap();
+        let Buckets {
+            global,
+            chats,
+            groups,
+        } = &mut *buckets;
+
+        if chats.len() > PRUNE_THRESHOLD {
+            chats.retain(|_, bucket| {
+                bucket.refill(now);
+                !bucket.is_full()
+            });
+        }
+        if groups.len() > PRUNE_THRESHOLD {
+            groups.retain(|_, bucket| {
+                bucket.refill(now);
+                !bucket.is_full()
+            });
+        }
+
+        let chat = chats
+            .entry(chat_id)
+            .or_insert_with(|| TokenBucket::new(options.chat_burst, options.chat_per_second, now));
+        let mut group = (chat_id < 0).then(|| {
+            groups.entry(chat_id).or_insert_with(|| {
+                TokenBucket::new(options.group_burst, options.group_per_minute / 60.0, now)
+            })
+        });
+
+        let wait = [
+            Some(chat.wait_time(now)),
+            group.as_mut().map(|group| group.wait_time(now)),
+            Some(global.wait_time(now)),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .unwrap_or_default();
+
+        if wait.is_zero() {
+            chat.take();
+            if let Some(group) = group {
+                group.take();
+            }
+            global.take();
+        }
+        wait
+    }
+
+    /// Periodically publish the queue depth to `broker`.
+    pub fn spawn_metrics_reporter(self: &Arc<Self>, broker: Arc<dyn MessageBroker>) {
+        if !self.options.enabled || self.options.metrics_interval_secs == 0 {
+            return;
+        }
+        let limiter = Arc::downgrade(self);
+        let period = Duration::from_secs(self.options.metrics_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                let Some(limiter) = limiter.upgrade() else {
+                    break;
+                };
+                let metrics = RateLimitMetrics {
+                    queue_depth: limiter.queue_depth(),
+                    timestamp: Utc::now(),
+                };
+                if metrics.queue_depth > 0 {
+                    tracing::info!(
+                        queue_depth = metrics.queue_depth,
+                        "Outgoing messages waiting for rate limit"
+                    );
+                }
+                let published = match serde_json::to_vec(&metrics) {
+                    Ok(payload) => broker.publish_metrics(&payload).await,
+                    Err(e) => Err(e.into()),
+                };
+                if let Err(e) = published {
+                    tracing::warn!(error = %e, "Failed to publish rate limit metrics");
+                }
+            }
+        });
+    }
+}
+
+impl Default for RateLimiter {
+    fn default() -> Self {
+        Self::new(RateLimitOptions::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter() -> RateLimiter {
+        RateLimiter::new(RateLimitOptions {
+            chat_per_second: 1.0,
+            chat_burst: 2,
+            group_per_minute: 6.0,
+            group_burst: 1,
+            global_per_second: 10.0,
+            global_burst: 3,
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn chat_bucket_allows_burst_then_waits_for_refill() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire(1, now).is_zero());
+        assert!(limiter.try_acquire(1, now).is_zero());
+        assert_eq!(limiter.try_acquire(1, now), Duration::from_secs(1));
+        assert!(
+            limiter
+                .try_acquire(1, now + Duration::from_secs(1))
+                .is_zero()
+        );
+    }
+
+    #[test]
+    fn groups_use_the_slower_group_bucket() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        assert!(limiter.try_acquire(-100, now).is_zero());
+        // 6 per minute: one token every 10 seconds.
+        assert_eq!(limiter.try_acquire(-100, now), Duration::from_secs(10));
+    }
+
+    #[test]
+    fn global_bucket_is_shared_by_all_chats() {
+        let limiter = limiter();
+        let now = Instant::now();
+
+        for chat_id in 1..=3 {
+            assert!(limiter.try_acquire(chat_id, now).is_zero());
+        }
+        assert_eq!(limiter.try_acquire(4, now), Duration::from_millis(100));
+    }
+
+    #[tokio::test]
+    async fn acquire_queues_instead_of_failing() {
+        let limiter = Arc::new(RateLimiter::new(RateLimitOptions {
+            chat_per_second: 20.0,
+            chat_burst: 1,
+            ..Default::default()
+        }));
+        limiter.acquire(1).await;
+
+        let start = Instant::now();
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(1).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(limiter.queue_depth(), 1);
+
+        waiter.await.unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(40));
+        assert_eq!(limiter.queue_depth(), 0);
+    }
+}
@@ -0,0 +1,70 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Outcome of an outgoing message.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    /// Telegram accepted the message
+    Delivered,
+    /// The message could not be delivered and went to the dead-letter queue
+    Failed,
+}
+
+/// Published to the receipts topic (`{prefix}.receipts`) after every outgoing
+/// message, so that backends learn the Telegram message IDs of their replies.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryReceipt {
+    /// Trace ID of the outgoing message this receipt is for
+    pub trace_id: Uuid,
+    pub chat_id: i64,
+    /// IDs of the Telegram messages that were sent, edited or deleted; for a
+    /// failure, the parts sent before it failed
+    pub message_ids: Vec<i32>,
+    pub status: DeliveryStatus,
+    /// Error message when `status` is `failed`
+    pub error: Option<String>,
+    /// ID of the poll sent by a `PollMessage`, as found in `Poll` and
+    /// `PollAnswer` updates
+    #[serde(default)]
+    pub poll_id: Option<String>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl DeliveryReceipt {
+    pub fn delivered(trace_id: Uuid, chat_id: i64, message_ids: Vec<i32>) -> Self {
+        Self {
+            trace_id,
+            chat_id,
+            message_ids,
+            status: DeliveryStatus::Delivered,
+            error: None,
+            poll_id: None,
+            timestamp: Utc::now(),
+        }
+    }
+
+    pub fn with_poll_id(mut self, poll_id: Option<String>) -> Self {
+        self.poll_id = poll_id;
+        self
+    }
+
+    /// Report the messages a failed message sent before it failed.
+    pub fn with_message_ids(mut self, message_ids: Vec<i32>) -> Self {
+        self.message_ids = message_ids;
+        self
+    }
+
+    pub fn failed(trace_id: Uuid, chat_id: i64, error: impl Into<String>) -> Self {
+        Self {
+            trace_id,
+            chat_id,
+            message_ids: Vec::new(),
+            status: DeliveryStatus::Failed,
+            error: Some(error.into()),
+            poll_id: None,
+            timestamp: Utc::now(),
+        }
+    }
+}
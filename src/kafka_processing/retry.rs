@@ -0,0 +1,209 @@
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+use teloxide::requests::{HasPayload, Output, Payload, Request};
+use teloxide::{ApiError, RequestError};
+
+/// Retry limits for Bot API calls (`[retry]` section of the config file).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct RetryPolicy {
+    /// Attempts per Bot API call, including the first one
+    pub max_attempts: u32,
+    /// Delay before the first retry of a transient error; doubled on every further retry
+    pub initial_backoff_ms: u64,
+    /// Upper bound for the exponential backoff
+    pub max_backoff_ms: u64,
+    /// Give up instead of waiting when Telegram's flood control asks for a longer pause
+    pub max_retry_after_secs: u64,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            initial_backoff_ms: 500,
+            max_backoff_ms: 10_000,
+            max_retry_after_secs: 60,
+        }
+    }
+}
+
+/// A Bot API error returned once the retry policy gave up.
+#[derive(Debug)]
+pub struct RetryError {
+    /// Attempts made, including the first one
+    pub attempts: u32,
+    pub source: RequestError,
+}
+
+impl RetryError {
+    /// Attempts behind `error`; 1 for errors that never went through a retry policy.
+    pub fn attempts_of(error: &(dyn std::error::Error + 'static)) -> u32 {
+        Self::find(error).map_or(1, |error| error.attempts)
+    }
+
+    /// The retry error that is `error` or one of its sources.
+    pub fn find<'a>(error: &'a (dyn std::error::Error + 'static)) -> Option<&'a RetryError> {
+        std::iter::successors(Some(error), |error| error.source())
+            .find_map(|error| error.downcast_ref::<RetryError>())
+    }
+}
+
+impl std::fmt::Display for RetryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.source.fmt(f)
+    }
+}
+
+impl std::error::Error for RetryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+impl RetryPolicy {
+    /// Send `request`, retrying flood-control and transient errors.
+    ///
+    /// Attempts are logged in the caller's span.
+    pub async fn send<R>(&self, request: &R) -> Result<Output<R>, RetryError>
+    where
+        R: Request<Err = RequestError>,
+    {
+        let method = <R as HasPayload>::Payload::NAME;
+        let mut attempts = 0;
+        loop {
+            attempts += 1;
+            let error = match request.send_ref().await {
+                Ok(output) => {
+                    if attempts > 1 {
+                        tracing::info!(method, attempts, "Bot API call succeeded after retrying");
+                    }
+                    return Ok(output);
+                }
+                Err(error) => error,
+            };
+
+            let Some(delay) = self.delay_for(&error, attempts) else {
+                if attempts > 1 {
+                    tracing::warn!(method, attempts, error = %error, "Giving up on Bot API call");
+                }
+                return Err(RetryError {
+                    attempts,
+                    source: error,
+                });
+            };
+            tracing::warn!(
+                method,
+                attempt = attempts,
+                max_attempts = self.max_attempts,
+                delay_ms = delay.as_millis() as u64,
+                error = %error,
+                "Bot API call failed, retrying"
+            );
+            tokio::time::sleep(delay).await;
+        }
+    }
+
+    /// How long to wait before retrying after `attempts` failed attempts, or
+    /// `None` if `error` must not be retried.
+    fn delay_for(&self, error: &RequestError, attempts: u32) -> Option<Duration> {
+        if attempts >= self.max_attempts {
+            return None;
+        }
+        match error {
+            RequestError::RetryAfter(seconds) => {
+                let seconds = u64::from(seconds.seconds());
+                (seconds <= self.max_retry_after_secs).then(|| Duration::from_secs(seconds))
+            }
+            // A 5xx with an HTML body surfaces as InvalidJson.
+            RequestError::Network(_) | RequestError::InvalidJson { .. } => {
+                Some(self.backoff(attempts))
+            }
+            RequestError::Api(ApiError::Unknown(description)) if is_server_error(description) => {
+                Some(self.backoff(attempts))
+            }
+            // 4xx responses, chat migrations and local I/O errors will fail again.
+            _ => None,
+        }
+    }
+
+    fn backoff(&self, attempts: u32) -> Duration {
+        let factor = 1u64
+            .checked_shl(attempts.saturating_sub(1))
+            .unwrap_or(u64::MAX);
+        let delay_ms = self
+            .initial_backoff_ms
+            .saturating_mul(factor)
+            .min(self.max_backoff_ms);
+        Duration::from_millis(delay_ms)
+    }
+}
+
+/// Telegram reports 5xx responses with a JSON body through the description only.
+fn is_server_error(description: &str) -> bool {
+    [
+        "Internal Server Error",
+        "Bad Gateway",
+        "Service Unavailable",
+        "Gateway Timeout",
+    ]
+    .iter()
+    .any(|status| description.contains(status))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::Seconds;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 4,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 250,
+            max_retry_after_secs: 30,
+        }
+    }
+
+    fn server_error() -> RequestError {
+        RequestError::Api(ApiError::Unknown("Internal Server Error".to_string()))
+    }
+
+    #[test]
+    fn transient_errors_back_off_exponentially_up_to_the_cap() {
+        let policy = policy();
+        assert_eq!(
+            policy.delay_for(&server_error(), 1),
+            Some(Duration::from_millis(100))
+        );
+        assert_eq!(
+            policy.delay_for(&server_error(), 2),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(
+            policy.delay_for(&server_error(), 3),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(policy.delay_for(&server_error(), 4), None);
+    }
+
+    #[test]
+    fn retry_after_is_honored_within_the_limit() {
+        let policy = policy();
+        let flood = RequestError::RetryAfter(Seconds::from_seconds(7));
+        assert_eq!(policy.delay_for(&flood, 1), Some(Duration::from_secs(7)));
+
+        let long_flood = RequestError::RetryAfter(Seconds::from_seconds(31));
+        assert_eq!(policy.delay_for(&long_flood, 1), None);
+    }
+
+    #[test]
+    fn client_errors_are_never_retried() {
+        let policy = policy();
+        let bad_request = RequestError::Api(ApiError::MessageTextIsEmpty);
+        assert_eq!(policy.delay_for(&bad_request, 1), None);
+
+        let blocked = RequestError::Api(ApiError::BotBlocked);
+        assert_eq!(policy.delay_for(&blocked, 1), None);
+    }
+}
@@ -0,0 +1,231 @@
+//! Splitting texts and captions that exceed Telegram's length limits.
+//!
+//! Lengths are counted the way Telegram counts them: in UTF-16 code units of
+//! the text after entity parsing, so HTML tags are free and `&amp;` is one
+//! character.
+
+/// Longest text Telegram accepts in a single message.
+pub const TEXT_MAX_LEN: usize = 4096;
+
+/// Longest caption Telegram accepts on a media message.
+pub const CAPTION_MAX_LEN: usize = 1024;
+
+/// Boundaries to cut at, best first.
+const SEPARATORS: [&str; 4] = ["\n\n", "\n", ". ", " "];
+
+/// Byte offset at which to cut plain `text` so that the first part is at most
+/// `max_len` long, or `None` if it fits as is. Paragraph, line, sentence and
+/// word boundaries are preferred over a hard cut.
+pub(crate) fn split_point(text: &str, max_len: usize) -> Option<usize> {
+    let mut len = 0;
+    let (limit, _) = text.char_indices().find(|(_, c)| {
+        len += c.len_utf16();
+        len > max_len
+    })?;
+    Some(best_cut(text, limit))
+}
+
+/// Split plain `text` into parts of at most `max_len`.
+pub(crate) fn split_text(text: &str, max_len: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = text;
+    while let Some(cut) = split_point(rest, max_len) {
+        parts.push(rest[..cut].to_string());
+        rest = &rest[cut..];
+    }
+    parts.push(rest.to_string());
+    parts
+}
+
+/// Split Telegram HTML into parts of at most `max_len`. Tags still open at a
+/// cut are closed at the end of the part and reopened at the start of the next.
+pub(crate) fn split_html(html: &str, max_len: usize) -> Vec<String> {
+    split_html_with(html, max_len, max_len)
+}
+
+/// Split a caption rendered to Telegram HTML into the part that fits on the
+/// media and the rest, which goes into follow-up text messages.
+pub(crate) fn split_caption(html: &str) -> (String, Vec<String>) {
+    let mut parts = split_html_with(html, CAPTION_MAX_LEN, TEXT_MAX_LEN).into_iter();
+    let caption = parts.next().unwrap_or_default();
+    (caption, parts.collect())
+}
+
+/// Telegram HTML reduced to its text, e.g. to resend a part without formatting.
+pub(crate) fn strip_html(html: &str) -> String {
+    let mut text = String::with_capacity(html.len());
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        text.push_str(&rest[..start]);
+        rest = rest[start..]
+            .find('>')
+            .map_or("", |end| &rest[start + end + 1..]);
+    }
+    text.push_str(rest);
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&amp;", "&")
+}
+
+fn split_html_with(html: &str, first_max_len: usize, max_len: usize) -> Vec<String> {
+    let mut parts: Vec<String> = Vec::new();
+    let mut open_tags: Vec<&str> = Vec::new();
+    let mut start = 0;
+    loop {
+        let limit = if parts.is_empty() {
+            first_max_len
+        } else {
+            max_len
+        };
+        let reopened = open_tags.concat();
+        let Some(cut) = html_split_point(&html[start..], limit) else {
+            parts.push(format!("{reopened}{}", &html[start..]));
+            return parts;
+        };
+        let part = &html[start..start + cut];
+        track_tags(part, &mut open_tags);
+        let closed: String = open_tags
+            .iter()
+            .rev()
+            .map(|tag| format!("</{}>", tag_name(tag)))
+            .collect();
+        parts.push(format!("{reopened}{part}{closed}"));
+        start += cut;
+    }
+}
+
+/// Like `split_point`, for Telegram HTML. Cuts never land inside a tag or an
+/// entity.
+fn html_split_point(html: &str, max_len: usize) -> Option<usize> {
+    let mut len = 0;
+    let mut i = 0;
+    while i < html.len() {
+        let rest = &html[i..];
+        let (units, size) = if rest.starts_with('<') {
+            (0, rest.find('>').map_or(rest.len(), |end| end + 1))
+        } else if let Some(end) = rest.starts_with('&').then(|| rest.find(';')).flatten() {
+            (1, end + 1)
+        } else {
+            let c = rest.chars().next().expect("i is a char boundary");
+            (c.len_utf16(), c.len_utf8())
+        };
+        if len + units > max_len {
+            return Some(best_cut(html, i));
+        }
+        len += units;
+        i += size;
+    }
+    None
+}
+
+/// The best place to cut `text` before byte offset `limit`; `limit` itself if
+/// there is no boundary in the second half.
+fn best_cut(text: &str, limit: usize) -> usize {
+    let window = &text[..limit];
+    SEPARATORS
+        .iter()
+        .filter_map(|separator| window.rfind(separator).map(|i| i + separator.len()))
+        // Cutting near the start would only produce a tiny part.
+        .find(|&cut| cut > limit / 2 && !inside_tag(window, cut))
+        .unwrap_or(limit)
+}
+
+fn inside_tag(html: &str, pos: usize) -> bool {
+    html[..pos].rfind('<') > html[..pos].rfind('>')
+}
+
+/// Update the stack of open tags with the tags in `html`.
+fn track_tags<'a>(html: &'a str, open_tags: &mut Vec<&'a str>) {
+    let mut rest = html;
+    while let Some(start) = rest.find('<') {
+        let Some(end) = rest[start..].find('>') else {
+            return;
+        };
+        let tag = &rest[start..start + end + 1];
+        if tag.starts_with("</") {
+            let name = tag_name(tag);
+            if let Some(i) = open_tags.iter().rposition(|open| tag_name(open) == name) {
+                open_tags.remove(i);
+            }
+        } else if !tag.ends_with("/>") {
+            open_tags.push(tag);
+        }
+        rest = &rest[start + end + 1..];
+    }
+}
+
+fn tag_name(tag: &str) -> &str {
+    tag.trim_start_matches('<')
+        .trim_start_matches('/')
+        .split(|c: char| c.is_whitespace() || c == '>' || c == '/')
+        .next()
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn visible_len(html: &str) -> usize {
+        strip_html(html).encode_utf16().count()
+    }
+
+    #[test]
+    fn long_text_is_split_at_the_best_boundary() {
+        assert_eq!(split_point("short", 10), None);
+        assert_eq!(split_point("0123456789", 10), None);
+
+        let text = format!("intro\n\n{}", "word ".repeat(10));
+        assert_eq!(split_point(&text, 12), Some(7));
+        // The paragraph break is too close to the start of a longer part.
+        assert_eq!(split_point(&text, 40), Some(37));
+
+        assert_eq!(split_point(&"x".repeat(12), 5), Some(5));
+        // Cuts never land inside a character, and emoji count twice.
+        assert_eq!(split_point(&"é".repeat(6), 4), Some(8));
+        assert_eq!(split_point(&"😀".repeat(3), 4), Some(8));
+
+        let parts = split_text(&"Sentence one. ".repeat(100), 100);
+        assert!(parts.iter().all(|part| part.len() <= 100));
+        assert!(parts[0].ends_with(". "));
+        assert_eq!(parts.concat(), "Sentence one. ".repeat(100));
+    }
+
+    #[test]
+    fn html_parts_stay_balanced() {
+        let html = format!("<b>Title</b>\n<pre>{}</pre>", "let x = 1;\n".repeat(50));
+        let parts = split_html(&html, 200);
+        assert!(parts.len() > 1);
+        for part in &parts {
+            assert!(visible_len(part) <= 200, "{part}");
+            assert_eq!(
+                part.matches("<pre>").count(),
+                part.matches("</pre>").count()
+            );
+        }
+        assert!(parts[0].starts_with("<b>Title</b>\n<pre>let x"));
+        assert!(parts[1].starts_with("<pre>let x"));
+        assert_eq!(strip_html(&parts.concat()), strip_html(&html));
+
+        // Entities count as one character and are never cut.
+        let parts = split_html(&"a&amp;b ".repeat(10), 12);
+        assert!(parts.iter().all(|part| visible_len(part) <= 12));
+        assert_eq!(parts[0], "a&amp;b ".repeat(3));
+        assert_eq!(strip_html("<i>1 &lt; 2 &amp;&amp; 3</i>"), "1 < 2 && 3");
+    }
+
+    #[test]
+    fn long_captions_overflow_into_follow_ups() {
+        let (caption, follow_ups) = split_caption("<i>short</i>");
+        assert_eq!(caption, "<i>short</i>");
+        assert!(follow_ups.is_empty());
+
+        let long = format!("<i>{}</i>", "word ".repeat(1000));
+        let (caption, follow_ups) = split_caption(&long);
+        assert!(visible_len(&caption) <= CAPTION_MAX_LEN);
+        assert!(caption.ends_with("</i>"));
+        assert_eq!(follow_ups.len(), 1);
+        assert!(follow_ups[0].starts_with("<i>word"));
+    }
+}
@@ -0,0 +1,403 @@
+//! Live-edited messages for output that arrives in pieces, e.g. an LLM answer
+//! streamed token by token.
+//!
+//! A stream starts as one message that is edited as text is appended. Edits are
+//! coalesced to at most one per `edit_interval_ms`, and once the text no longer
+//! fits into a message the current one is sealed and the rest continues in a
+//! new message.
+
+use super::outgoing::{SendOptions, StreamAction, StreamMessageData};
+use super::payload::prepare;
+use super::retry::RetryError;
+use super::split::{TEXT_MAX_LEN, split_point};
+use super::{OutgoingContext, send_with_markup};
+use crate::utils::{create_markup, format_telegram_markdown};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::payloads::{EditMessageTextSetters, SendMessageSetters};
+use teloxide::prelude::{ChatId, Requester};
+use teloxide::types::{MessageId, ParseMode};
+use teloxide::{ApiError, RequestError};
+use tokio::time::Instant;
+use tracing::Instrument;
+
+/// Shown while a stream has no text yet.
+const PLACEHOLDER: &str = "…";
+
+const FENCE: &str = "```";
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+type SharedStream = Arc<tokio::sync::Mutex<StreamState>>;
+
+/// Streaming limits (`[stream]` section of the config file).
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct StreamOptions {
+    /// Minimum time between two edits of a streamed message
+    pub edit_interval_ms: u64,
+    /// Streams that received nothing for this long are forgotten without a final edit
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for StreamOptions {
+    fn default() -> Self {
+        Self {
+            edit_interval_ms: 1000,
+            idle_timeout_secs: 600,
+        }
+    }
+}
+
+/// The open streams, keyed by chat and stream ID.
+#[derive(Default)]
+pub struct StreamRegistry {
+    options: StreamOptions,
+    streams: Mutex<HashMap<(i64, String), SharedStream>>,
+}
+
+impl StreamRegistry {
+    pub fn new(options: StreamOptions) -> Self {
+        Self {
+            options,
+            streams: Mutex::default(),
+        }
+    }
+
+    /// Apply one stream event, returning the IDs of all messages of the stream.
+    pub(crate) async fn handle(
+        &self,
+        ctx: &OutgoingContext,
+        chat_id: ChatId,
+        thread_id: Option<i32>,
+        data: StreamMessageData,
+    ) -> Result<Vec<i32>, BoxError> {
+        self.prune_idle();
+        let key = (chat_id.0, data.stream_id.clone());
+        let existing = self.streams.lock().unwrap().get(&key).cloned();
+
+        // `append` and `finish` open the stream themselves if `start` was skipped.
+        let (stream, opened) = match existing {
+            Some(stream) => (stream, false),
+            None => {
+                if data.action == StreamAction::Finish && data.text.is_none() {
+                    return Err(format!("Stream {} is not open", data.stream_id).into());
+                }
+                let mut state = StreamState::new(chat_id, thread_id, &data);
+                state.open(ctx).await?;
+                let stream = Arc::new(tokio::sync::Mutex::new(state));
+                self.streams
+                    .lock()
+                    .unwrap()
+                    .insert(key.clone(), stream.clone());
+                (stream, true)
+            }
+        };
+
+        let mut state = stream.lock().await;
+        state.touched = Instant::now();
+        match data.action {
+            StreamAction::Start => {
+                if !opened {
+                    tracing::debug!(stream_id = %data.stream_id, "Stream is already open");
+                }
+            }
+            StreamAction::Append => {
+                if !opened {
+                    state
+                        .text
+                        .push_str(data.text.as_deref().unwrap_or_default());
+                    self.flush_or_schedule(ctx, &stream, &mut state).await?;
+                }
+            }
+            StreamAction::Finish => {
+                if !opened {
+                    state
+                        .text
+                        .push_str(data.text.as_deref().unwrap_or_default());
+                }
+                state.finished = true;
+                self.streams.lock().unwrap().remove(&key);
+                state.flush(ctx).await?;
+                if let Some(markup) = create_markup(&data.buttons) {
+                    let last = *state
+                        .message_ids
+                        .last()
+                        .expect("open streams have a message");
+                    ctx.rate_limiter.acquire(chat_id.0).await;
+                    let edit = ctx.bot.edit_message_reply_markup(chat_id, MessageId(last));
+                    send_with_markup(ctx, edit, Some(markup)).await?;
+                }
+                tracing::info!(
+                    stream_id = %data.stream_id,
+                    text_length = state.text.len(),
+                    messages = state.message_ids.len(),
+                    "Finished stream"
+                );
+            }
+        }
+        Ok(state.message_ids.clone())
+    }
+
+    /// Flush now if the last edit is long enough ago, otherwise make sure a
+    /// flush is scheduled for when it is.
+    async fn flush_or_schedule(
+        &self,
+        ctx: &OutgoingContext,
+        stream: &SharedStream,
+        state: &mut StreamState,
+    ) -> Result<(), BoxError> {
+        let due = state.last_edit + Duration::from_millis(self.options.edit_interval_ms);
+        if Instant::now() >= due {
+            return state.flush(ctx).await;
+        }
+        if state.flush_scheduled {
+            return Ok(());
+        }
+        state.flush_scheduled = true;
+        let ctx = ctx.clone();
+        let stream = stream.clone();
+        tokio::spawn(
+            async move {
+                tokio::time::sleep_until(due).await;
+                let mut state = stream.lock().await;
+                state.flush_scheduled = false;
+                // `finish` already did the final flush.
+                if state.finished {
+                    return;
+                }
+                if let Err(e) = state.flush(&ctx).await {
+                    tracing::warn!(error = %e, "Failed to update streamed message");
+                }
+            }
+            .instrument(tracing::Span::current()),
+        );
+        Ok(())
+    }
+
+    /// Forget streams that were not touched for `idle_timeout_secs`. Streams
+    /// busy with a flush are skipped.
+    fn prune_idle(&self) {
+        let timeout = Duration::from_secs(self.options.idle_timeout_secs);
+        self.streams
+            .lock()
+            .unwrap()
+            .retain(|(chat_id, stream_id), stream| match stream.try_lock() {
+                Ok(state) if state.touched.elapsed() > timeout => {
+                    tracing::warn!(chat_id, stream_id = %stream_id, "Dropping idle stream");
+                    false
+                }
+                _ => true,
+            });
+    }
+}
+
+struct StreamState {
+    chat_id: ChatId,
+    thread_id: Option<i32>,
+    options: SendOptions,
+    formatted: bool,
+    text: String,
+    /// Byte offset in `text` where the current (last) message begins
+    current_start: usize,
+    message_ids: Vec<i32>,
+    /// Source text the current message shows
+    shown: String,
+    last_edit: Instant,
+    touched: Instant,
+    flush_scheduled: bool,
+    finished: bool,
+}
+
+impl StreamState {
+    fn new(chat_id: ChatId, thread_id: Option<i32>, data: &StreamMessageData) -> Self {
+        let now = Instant::now();
+        Self {
+            chat_id,
+            thread_id,
+            options: data.options.clone(),
+            formatted: matches!(data.parse_mode.as_deref(), Some("HTML") | Some("Markdown")),
+            text: data.text.clone().unwrap_or_default(),
+            current_start: 0,
+            message_ids: Vec::new(),
+            shown: String::new(),
+            last_edit: now,
+            touched: now,
+            flush_scheduled: false,
+            finished: false,
+        }
+    }
+
+    /// Send the first message, continuing in further messages if the initial
+    /// text is too long for one.
+    async fn open(&mut self, ctx: &OutgoingContext) -> Result<(), BoxError> {
+        let (body, _) = self.current_piece();
+        let message_id = self.send(ctx, &body).await?;
+        self.message_ids.push(message_id);
+        self.shown = body;
+        self.flush(ctx).await
+    }
+
+    /// Bring the messages up to date with `text`.
+    async fn flush(&mut self, ctx: &OutgoingContext) -> Result<(), BoxError> {
+        loop {
+            let (body, next_start) = self.current_piece();
+            if body != self.shown {
+                let current = *self
+                    .message_ids
+                    .last()
+                    .expect("open streams have a message");
+                self.edit(ctx, current, &body).await?;
+                self.shown = body;
+            }
+            let Some(next_start) = next_start else {
+                break;
+            };
+            self.current_start = next_start;
+            let (body, _) = self.current_piece();
+            let message_id = self.send(ctx, &body).await?;
+            self.message_ids.push(message_id);
+            self.shown = body;
+        }
+        self.last_edit = Instant::now();
+        Ok(())
+    }
+
+    /// Source text of the current message, and where the next message starts
+    /// if the rest does not fit.
+    fn current_piece(&self) -> (String, Option<usize>) {
+        // A code block cut by the previous message is reopened.
+        let prefix = if self.formatted && fence_open(&self.text[..self.current_start]) {
+            "```\n"
+        } else {
+            ""
+        };
+        let rest = &self.text[self.current_start..];
+        let (piece, next_start) = match split_point(rest, TEXT_MAX_LEN - prefix.len()) {
+            Some(cut) => (&rest[..cut], Some(self.current_start + cut)),
+            None => (rest, None),
+        };
+        if piece.trim().is_empty() {
+            return (PLACEHOLDER.to_string(), next_start);
+        }
+        (format!("{prefix}{piece}"), next_start)
+    }
+
+    /// Telegram HTML for `body`. Partial text may end inside a code block,
+    /// which is closed so that it renders as one.
+    fn render(&self, body: &str) -> String {
+        if fence_open(body) {
+            format_telegram_markdown(&format!("{body}\n{FENCE}"))
+        } else {
+            format_telegram_markdown(body)
+        }
+    }
+
+    async fn send(&self, ctx: &OutgoingContext, body: &str) -> Result<i32, BoxError> {
+        // Replies and quotes belong to the first message only.
+        let options = if self.message_ids.is_empty() {
+            self.options.clone()
+        } else {
+            self.options.follow_up()
+        };
+        ctx.rate_limiter.acquire(self.chat_id.0).await;
+        let plain = prepare(
+            ctx.bot.send_message(self.chat_id, body),
+            self.thread_id,
+            &options,
+        );
+        let sent = if self.formatted {
+            let formatted = prepare(
+                ctx.bot.send_message(self.chat_id, self.render(body)),
+                self.thread_id,
+                &options,
+            )
+            .parse_mode(ParseMode::Html);
+            super::try_send_with_fallback(
+                ctx.retry.send(&formatted).await,
+                || ctx.retry.send(&plain),
+                "stream message",
+            )
+            .await?
+        } else {
+            ctx.retry.send(&plain).await?
+        };
+        Ok(sent.id.0)
+    }
+
+    async fn edit(
+        &self,
+        ctx: &OutgoingContext,
+        message_id: i32,
+        body: &str,
+    ) -> Result<(), BoxError> {
+        ctx.rate_limiter.acquire(self.chat_id.0).await;
+        let plain = ctx
+            .bot
+            .edit_message_text(self.chat_id, MessageId(message_id), body);
+        let result = if self.formatted {
+            let formatted = ctx
+                .bot
+                .edit_message_text(self.chat_id, MessageId(message_id), self.render(body))
+                .parse_mode(ParseMode::Html);
+            super::try_send_with_fallback(
+                ctx.retry.send(&formatted).await,
+                || ctx.retry.send(&plain),
+                "stream edit",
+            )
+            .await
+        } else {
+            ctx.retry.send(&plain).await
+        };
+        match result {
+            Ok(_) => Ok(()),
+            Err(e) if not_modified(&e) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+fn not_modified(error: &RetryError) -> bool {
+    matches!(
+        error.source,
+        RequestError::Api(ApiError::MessageNotModified)
+    )
+}
+
+/// Whether `text` ends inside a ``` code block.
+fn fence_open(text: &str) -> bool {
+    text.matches(FENCE).count() % 2 == 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_code_blocks_are_closed_and_reopened() {
+        let data: StreamMessageData = serde_json::from_value(serde_json::json!({
+            "stream_id": "s1",
+            "action": "start",
+            "parse_mode": "Markdown"
+        }))
+        .unwrap();
+        let mut state = StreamState::new(ChatId(1), None, &data);
+        assert_eq!(state.current_piece(), (PLACEHOLDER.to_string(), None));
+
+        state.text = format!("Here:\n```rust\n{}", "let x = 1;\n".repeat(400));
+        let (first, next_start) = state.current_piece();
+        assert!(first.encode_utf16().count() <= TEXT_MAX_LEN);
+        assert_eq!(
+            state.render(&first),
+            format!("Here:\n<pre>{}\n</pre>", &first[14..])
+        );
+
+        state.current_start = next_start.unwrap();
+        let (second, next_start) = state.current_piece();
+        assert_eq!(next_start, None);
+        assert!(second.starts_with("```\nlet x = 1;"));
+        assert!(state.render(&second).starts_with("<pre>let x = 1;"));
+    }
+}
@@ -5,3 +5,6 @@ pub mod kafka_processing;
 pub mod telegram_handler;
 pub mod users;
 pub mod utils;
+
+pub use broker::MessageBroker;
+pub use broker::memory::InMemoryBroker;
@@ -12,37 +12,30 @@ use tracing_subscriber::{EnvFilter, fmt, layer::SubscriberExt, util::SubscriberI
 mod cli;
 use cli::{Cli, Command, UsersAction};
 
-mod telegram_handler;
-use telegram_handler::{
-    callback_query_handler, edited_message_handler, message_handler, message_reaction_handler,
+use ratatoskr::auth::AuthService;
+use ratatoskr::broker::{self, BrokerKind};
+use ratatoskr::config::{ServeConfig, UsersConfig};
+use ratatoskr::kafka_processing::callback::CallbackAnswers;
+use ratatoskr::kafka_processing::{OutgoingContext, start_broker_consumer_loop};
+use ratatoskr::telegram_handler::{
+    callback_query_handler, chat_join_request_handler, chat_member_handler, edited_message_handler,
+    message_handler, message_reaction_handler, poll_answer_handler, poll_handler,
 };
-
-mod utils;
-
-mod broker;
-use broker::{MessageBroker, kafka::KafkaBroker};
-mod kafka_processing;
-use kafka_processing::*;
-
-mod auth;
-use auth::AuthService;
-mod config;
-use config::UsersConfig;
-mod users;
+use ratatoskr::users;
 
 #[tokio::main]
 async fn main() {
     let cli = Cli::parse();
 
     match cli.command {
-        Command::Serve => run_serve(&cli).await,
+        Command::Serve { broker } => run_serve(&cli, broker).await,
         Command::Users { ref action } => run_users(&cli, action),
         Command::Send {
             chat_id,
             ref parse_mode,
             thread_id,
             ref message,
-        } => run_send(chat_id, parse_mode.as_deref(), thread_id, message),
+        } => run_send(&cli, chat_id, parse_mode.as_deref(), thread_id, message),
     }
 }
 
@@ -89,9 +82,15 @@ fn build_send_text(stdin_text: &str, message: &[String]) -> Result<String, Strin
     }
 }
 
-fn run_send(chat_id: i64, parse_mode: Option<&str>, thread_id: Option<i32>, message: &[String]) {
-    use kafka_processing::outgoing::{
-        MessageTarget, OutgoingMessage, OutgoingMessageType, TextMessageData,
+fn run_send(
+    cli: &Cli,
+    chat_id: i64,
+    parse_mode: Option<&str>,
+    thread_id: Option<i32>,
+    message: &[String],
+) {
+    use ratatoskr::kafka_processing::outgoing::{
+        MessageTarget, OutgoingMessage, OutgoingMessageType, SendOptions, TextMessageData,
     };
     use rdkafka::config::ClientConfig;
     use rdkafka::producer::{BaseProducer, BaseRecord, Producer};
@@ -114,10 +113,10 @@ fn run_send(chat_id: i64, parse_mode: Option<&str>, thread_id: Option<i32>, mess
         }
     };
 
-    let brokers = env::var("KAFKA_BROKERS").unwrap_or_else(|_| "localhost:9092".to_string());
-
-    let topic_prefix = env::var("KAFKA_TOPIC_PREFIX").unwrap_or_else(|_| "ratatoskr".to_string());
-    let topic = format!("{}.out", topic_prefix);
+    let mut config = load_serve_config(cli);
+    config.apply_env(|name| env::var(name).ok());
+    let brokers = config.kafka.brokers;
+    let topic = format!("{}.out", config.kafka.topic_prefix);
 
     let msg = OutgoingMessage {
         trace_id: uuid::Uuid::new_v4(),
@@ -125,8 +124,10 @@ fn run_send(chat_id: i64, parse_mode: Option<&str>, thread_id: Option<i32>, mess
             text,
             buttons: None,
             reply_keyboard: None,
+            reply_markup: None,
             parse_mode: parse_mode.map(String::from),
             disable_web_page_preview: None,
+            options: SendOptions::default(),
         }),
         timestamp: chrono::Utc::now(),
         target: MessageTarget {
@@ -166,36 +167,14 @@ fn run_send(chat_id: i64, parse_mode: Option<&str>, thread_id: Option<i32>, mess
     });
 }
 
-#[cfg(test)]
-mod tests {
-    use super::build_send_text;
-
-    #[test]
-    fn build_send_text_errors_on_empty_input() {
-        let text = build_send_text("", &[]);
-        assert!(text.is_err());
-    }
-
-    #[test]
-    fn build_send_text_uses_stdin_when_only_stdin() {
-        let text = build_send_text("hello\n", &[]).unwrap();
-        assert_eq!(text, "hello");
-    }
-
-    #[test]
-    fn build_send_text_uses_positional_when_only_positional() {
-        let text = build_send_text("", &["hello".to_string(), "world".to_string()]).unwrap();
-        assert_eq!(text, "hello world");
-    }
-
-    #[test]
-    fn build_send_text_appends_positional_after_stdin() {
-        let text = build_send_text("hello", &["world".to_string()]).unwrap();
-        assert_eq!(text, "hello\nworld");
-    }
+fn load_serve_config(cli: &Cli) -> ServeConfig {
+    ServeConfig::load(&cli.config).unwrap_or_else(|e| {
+        eprintln!("Error: {e:#}");
+        std::process::exit(1);
+    })
 }
 
-async fn run_serve(cli: &Cli) {
+async fn run_serve(cli: &Cli, broker_override: Option<BrokerKind>) {
     dotenv().ok();
 
     tracing_subscriber::registry()
@@ -223,47 +202,73 @@ async fn run_serve(cli: &Cli) {
 
     let telegram_token =
         env::var("TELEGRAM_BOT_TOKEN").expect("FATAL: TELEGRAM_BOT_TOKEN not set in environment");
-    let kafka_brokers =
-        env::var("KAFKA_BROKERS").unwrap_or_else(|_| "localhost:9092".to_string());
-    let kafka_topic_prefix = env::var("KAFKA_TOPIC_PREFIX").ok();
+    let mut config = load_serve_config(cli);
+    config.apply_env(|name| env::var(name).ok());
+    if let Some(kind) = broker_override {
+        config.broker = Some(kind);
+    }
 
     let bot = Bot::new(telegram_token.clone());
 
-    tracing::info!(
-        brokers = %kafka_brokers,
-        topic_prefix = ?kafka_topic_prefix,
-        "Using Kafka broker"
-    );
-    let kafka_broker = KafkaBroker::new(
-        &kafka_brokers,
-        kafka_topic_prefix.as_deref(),
-        None,
-    )
-    .expect("Failed to create Kafka broker");
-
-    // Ensure topics exist
-    kafka_broker
-        .ensure_topics()
+    let broker = broker::connect(&config)
         .await
-        .expect("Failed to create Kafka topics");
+        .expect("Failed to set up message broker");
 
-    let broker = Arc::new(kafka_broker) as Arc<dyn MessageBroker>;
+    let callback_answers = Arc::new(CallbackAnswers::new(config.callbacks.clone()));
 
     // Start consumer loop for outgoing messages
-    let bot_consumer_clone = bot.clone();
+    let outgoing_ctx = OutgoingContext::new(bot.clone())
+        .with_retry(config.retry.clone())
+        .with_rate_limits(config.rate_limit.clone())
+        .with_dispatch(config.dispatch.clone())
+        .with_streaming(config.stream.clone())
+        .with_callback_answers(callback_answers.clone());
     let broker_clone = broker.clone();
-    tokio::spawn(start_broker_consumer_loop(bot_consumer_clone, broker_clone));
+    tokio::spawn(start_broker_consumer_loop(outgoing_ctx, broker_clone));
 
     let handler = dptree::entry()
         .branch(Update::filter_message().endpoint(message_handler))
         .branch(Update::filter_edited_message().endpoint(edited_message_handler))
         .branch(Update::filter_callback_query().endpoint(callback_query_handler))
-        .branch(Update::filter_message_reaction_updated().endpoint(message_reaction_handler));
+        .branch(Update::filter_message_reaction_updated().endpoint(message_reaction_handler))
+        .branch(Update::filter_poll().endpoint(poll_handler))
+        .branch(Update::filter_poll_answer().endpoint(poll_answer_handler))
+        .branch(Update::filter_chat_join_request().endpoint(chat_join_request_handler))
+        .branch(Update::filter_chat_member().endpoint(chat_member_handler));
 
     Dispatcher::builder(bot, handler)
-        .dependencies(dptree::deps![broker, auth_service])
+        .dependencies(dptree::deps![broker, auth_service, callback_answers])
         .enable_ctrlc_handler()
         .build()
         .dispatch()
         .await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::build_send_text;
+
+    #[test]
+    fn build_send_text_errors_on_empty_input() {
+        let text = build_send_text("", &[]);
+        assert!(text.is_err());
+    }
+
+    #[test]
+    fn build_send_text_uses_stdin_when_only_stdin() {
+        let text = build_send_text("hello\n", &[]).unwrap();
+        assert_eq!(text, "hello");
+    }
+
+    #[test]
+    fn build_send_text_uses_positional_when_only_positional() {
+        let text = build_send_text("", &["hello".to_string(), "world".to_string()]).unwrap();
+        assert_eq!(text, "hello world");
+    }
+
+    #[test]
+    fn build_send_text_appends_positional_after_stdin() {
+        let text = build_send_text("hello", &["world".to_string()]).unwrap();
+        assert_eq!(text, "hello\nworld");
+    }
+}
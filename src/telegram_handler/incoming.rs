@@ -1,6 +1,9 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use teloxide::types::Message as TelegramMessage;
+use teloxide::types::{
+    ChatJoinRequest, ChatMemberKind, ChatMemberUpdated, MaybeAnonymousUser,
+    Message as TelegramMessage, Poll, PollAnswer, PollType,
+};
 use uuid::Uuid;
 
 // Unified incoming message type for the IN topic
@@ -19,6 +22,10 @@ pub enum IncomingMessageType {
     CallbackQuery(CallbackQueryData),
     MessageReaction(MessageReactionData),
     EditedMessage(EditedMessageData),
+    Poll(PollData),
+    PollAnswer(PollAnswerData),
+    ChatJoinRequest(ChatJoinRequestData),
+    ChatMember(ChatMemberData),
 }
 
 /// Data for incoming Telegram messages
@@ -28,6 +35,9 @@ pub struct TelegramMessageData {
     pub message: TelegramMessage,
     /// File attachments with download URLs - files are not downloaded yet
     pub file_attachments: Vec<FileInfo>,
+    /// Forum topic of the message; use it as `MessageTarget.thread_id` to reply in the same topic
+    #[serde(default)]
+    pub thread_id: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -37,6 +47,9 @@ pub struct CallbackQueryData {
     pub message_id: i32,
     pub callback_data: String,
     pub callback_query_id: String,
+    /// Forum topic of the message the button is attached to
+    #[serde(default)]
+    pub thread_id: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -57,6 +70,74 @@ pub struct EditedMessageData {
     pub file_attachments: Vec<FileInfo>,
     /// Edit date from Telegram (when the message was edited)
     pub edit_date: Option<i32>,
+    /// Forum topic of the message
+    #[serde(default)]
+    pub thread_id: Option<i32>,
+}
+
+/// Current state of a poll. Telegram only reports polls sent by the bot, when
+/// votes change or the poll is closed.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollData {
+    pub poll_id: String,
+    pub question: String,
+    pub options: Vec<PollOptionData>,
+    pub total_voter_count: u32,
+    pub is_closed: bool,
+    pub is_anonymous: bool,
+    pub poll_type: String, // "regular" or "quiz"
+    pub allows_multiple_answers: bool,
+    /// Correct option of a quiz, once it is closed
+    pub correct_option_id: Option<u8>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollOptionData {
+    pub text: String,
+    pub voter_count: u32,
+}
+
+/// A vote in a non-anonymous poll sent by the bot
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PollAnswerData {
+    pub poll_id: String,
+    pub user_id: Option<u64>,       // None if voting on behalf of a chat
+    pub voter_chat_id: Option<i64>, // Chat that voted anonymously
+    /// Chosen options, 0-based; empty when the vote was retracted
+    pub option_ids: Vec<u8>,
+}
+
+/// A user asking to join a chat in which the bot can approve members
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatJoinRequestData {
+    pub chat_id: i64,
+    pub user_id: u64,
+    pub username: Option<String>,
+    pub first_name: String,
+    pub last_name: Option<String>,
+    /// Private chat with the user; the bot may message them there until the request is handled
+    pub user_chat_id: i64,
+    pub bio: Option<String>,
+    pub invite_link: Option<String>, // Link the user followed, if any
+    pub date: DateTime<Utc>,
+}
+
+/// A change of a member's status in a chat the bot administers
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatMemberData {
+    pub chat_id: i64,
+    pub user_id: u64,
+    pub username: Option<String>,
+    pub first_name: String,
+    pub changed_by: u64, // The admin who made the change, or the member themselves
+    /// "creator", "administrator", "member", "restricted", "left" or "kicked"
+    pub old_status: String,
+    pub new_status: String,
+    /// Whether the user is in the chat after the change
+    pub is_member: bool,
+    pub invite_link: Option<String>, // Link the user joined by, if any
+    pub via_join_request: bool,
+    pub date: DateTime<Utc>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -135,6 +216,15 @@ pub enum FileMetadata {
     },
 }
 
+/// Forum topic a message was posted in. Supergroup reply threads outside of
+/// forums also carry a thread ID, but cannot be used as a send target.
+pub fn topic_thread_id(message: &TelegramMessage) -> Option<i32> {
+    message
+        .thread_id
+        .filter(|_| message.is_topic_message)
+        .map(|thread_id| thread_id.0.0)
+}
+
 // Helper implementations
 impl IncomingMessage {
     pub fn new_telegram_message(
@@ -146,6 +236,7 @@ impl IncomingMessage {
         Self {
             trace_id: Uuid::new_v4(),
             message_type: IncomingMessageType::TelegramMessage(TelegramMessageData {
+                thread_id: topic_thread_id(&message),
                 message,
                 file_attachments,
             }),
@@ -158,10 +249,12 @@ impl IncomingMessage {
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new_callback_query(
         chat_id: i64,
         user_id: u64,
         message_id: i32,
+        thread_id: Option<i32>,
         callback_data: String,
         callback_query_id: String,
         bot_id: Option<u64>,
@@ -175,6 +268,7 @@ impl IncomingMessage {
                 message_id,
                 callback_data,
                 callback_query_id,
+                thread_id,
             }),
             timestamp: Utc::now(),
             source: MessageSource {
@@ -225,6 +319,7 @@ impl IncomingMessage {
         Self {
             trace_id: Uuid::new_v4(),
             message_type: IncomingMessageType::EditedMessage(EditedMessageData {
+                thread_id: topic_thread_id(&message),
                 message,
                 file_attachments,
                 edit_date,
@@ -237,4 +332,139 @@ impl IncomingMessage {
             },
         }
     }
+
+    pub fn new_poll(poll: &Poll, bot_id: Option<u64>, bot_username: Option<String>) -> Self {
+        Self {
+            trace_id: Uuid::new_v4(),
+            message_type: IncomingMessageType::Poll(PollData {
+                poll_id: poll.id.clone(),
+                question: poll.question.clone(),
+                options: poll
+                    .options
+                    .iter()
+                    .map(|option| PollOptionData {
+                        text: option.text.clone(),
+                        voter_count: option.voter_count,
+                    })
+                    .collect(),
+                total_voter_count: poll.total_voter_count,
+                is_closed: poll.is_closed,
+                is_anonymous: poll.is_anonymous,
+                poll_type: match poll.poll_type {
+                    PollType::Quiz => "quiz",
+                    PollType::Regular => "regular",
+                }
+                .to_string(),
+                allows_multiple_answers: poll.allows_multiple_answers,
+                correct_option_id: poll.correct_option_id,
+            }),
+            timestamp: Utc::now(),
+            source: MessageSource {
+                platform: "telegram".to_string(),
+                bot_id,
+                bot_username,
+            },
+        }
+    }
+
+    pub fn new_poll_answer(
+        answer: &PollAnswer,
+        bot_id: Option<u64>,
+        bot_username: Option<String>,
+    ) -> Self {
+        let (user_id, voter_chat_id) = match &answer.voter {
+            MaybeAnonymousUser::User(user) => (Some(user.id.0), None),
+            MaybeAnonymousUser::Chat(chat) => (None, Some(chat.id.0)),
+        };
+        Self {
+            trace_id: Uuid::new_v4(),
+            message_type: IncomingMessageType::PollAnswer(PollAnswerData {
+                poll_id: answer.poll_id.clone(),
+                user_id,
+                voter_chat_id,
+                option_ids: answer.option_ids.clone(),
+            }),
+            timestamp: Utc::now(),
+            source: MessageSource {
+                platform: "telegram".to_string(),
+                bot_id,
+                bot_username,
+            },
+        }
+    }
+
+    pub fn new_chat_join_request(
+        request: &ChatJoinRequest,
+        bot_id: Option<u64>,
+        bot_username: Option<String>,
+    ) -> Self {
+        Self {
+            trace_id: Uuid::new_v4(),
+            message_type: IncomingMessageType::ChatJoinRequest(ChatJoinRequestData {
+                chat_id: request.chat.id.0,
+                user_id: request.from.id.0,
+                username: request.from.username.clone(),
+                first_name: request.from.first_name.clone(),
+                last_name: request.from.last_name.clone(),
+                user_chat_id: request.user_chat_id.0,
+                bio: request.bio.clone(),
+                invite_link: request
+                    .invite_link
+                    .as_ref()
+                    .map(|link| link.invite_link.clone()),
+                date: request.date,
+            }),
+            timestamp: Utc::now(),
+            source: MessageSource {
+                platform: "telegram".to_string(),
+                bot_id,
+                bot_username,
+            },
+        }
+    }
+
+    pub fn new_chat_member(
+        update: &ChatMemberUpdated,
+        bot_id: Option<u64>,
+        bot_username: Option<String>,
+    ) -> Self {
+        let user = &update.new_chat_member.user;
+        Self {
+            trace_id: Uuid::new_v4(),
+            message_type: IncomingMessageType::ChatMember(ChatMemberData {
+                chat_id: update.chat.id.0,
+                user_id: user.id.0,
+                username: user.username.clone(),
+                first_name: user.first_name.clone(),
+                changed_by: update.from.id.0,
+                old_status: member_status(&update.old_chat_member.kind).to_string(),
+                new_status: member_status(&update.new_chat_member.kind).to_string(),
+                is_member: update.new_chat_member.kind.is_present(),
+                invite_link: update
+                    .invite_link
+                    .as_ref()
+                    .map(|link| link.invite_link.clone()),
+                via_join_request: update.via_join_request,
+                date: update.date,
+            }),
+            timestamp: Utc::now(),
+            source: MessageSource {
+                platform: "telegram".to_string(),
+                bot_id,
+                bot_username,
+            },
+        }
+    }
+}
+
+/// The status of a chat member as the Bot API names it.
+fn member_status(kind: &ChatMemberKind) -> &'static str {
+    match kind {
+        ChatMemberKind::Owner(_) => "creator",
+        ChatMemberKind::Administrator(_) => "administrator",
+        ChatMemberKind::Member => "member",
+        ChatMemberKind::Restricted(_) => "restricted",
+        ChatMemberKind::Left => "left",
+        ChatMemberKind::Banned(_) => "kicked",
+    }
 }
@@ -1,15 +1,19 @@
 use crate::auth::AuthService;
 use crate::broker::MessageBroker;
+use crate::kafka_processing::callback::CallbackAnswers;
 use crate::utils::{
     file_info_from_animation, file_info_from_audio, file_info_from_document, file_info_from_photo,
     file_info_from_sticker, file_info_from_video, file_info_from_video_note, file_info_from_voice,
-    get_file_info, select_best_photo,
+    get_file_info, reaction_to_string, select_best_photo,
 };
 use anyhow::Result;
-use incoming::{FileInfo, IncomingMessage};
+use incoming::{FileInfo, IncomingMessage, topic_thread_id};
 use std::sync::Arc;
-use teloxide::prelude::{Bot, CallbackQuery, Message, Requester};
-use teloxide::types::MessageReactionUpdated;
+use teloxide::prelude::{Bot, CallbackQuery, Message};
+use teloxide::types::{
+    ChatJoinRequest, ChatMemberUpdated, MaybeAnonymousUser, MessageReactionUpdated, Poll,
+    PollAnswer,
+};
 use tokio::sync::RwLock;
 use tracing::Instrument;
 use uuid::Uuid;
@@ -328,29 +332,9 @@ pub async fn message_reaction_handler(
     }
 
     // Convert reaction types to strings
-    let old_reaction: Vec<String> = reaction
-        .old_reaction
-        .iter()
-        .map(|r| match r {
-            teloxide::types::ReactionType::Emoji { emoji } => emoji.clone(),
-            teloxide::types::ReactionType::CustomEmoji { custom_emoji_id } => {
-                format!("custom:{}", custom_emoji_id)
-            }
-            teloxide::types::ReactionType::Paid => "paid".to_string(),
-        })
-        .collect();
-
-    let new_reaction: Vec<String> = reaction
-        .new_reaction
-        .iter()
-        .map(|r| match r {
-            teloxide::types::ReactionType::Emoji { emoji } => emoji.clone(),
-            teloxide::types::ReactionType::CustomEmoji { custom_emoji_id } => {
-                format!("custom:{}", custom_emoji_id)
-            }
-            teloxide::types::ReactionType::Paid => "paid".to_string(),
-        })
-        .collect();
+    let old_reaction: Vec<String> = reaction.old_reaction.iter().map(reaction_to_string).collect();
+
+    let new_reaction: Vec<String> = reaction.new_reaction.iter().map(reaction_to_string).collect();
 
     tracing::info!(
         chat_id = %chat_id,
@@ -398,11 +382,13 @@ pub async fn callback_query_handler(
     query: CallbackQuery,
     producer: Arc<dyn MessageBroker>,
     auth: Arc<RwLock<AuthService>>,
+    answers: Arc<CallbackAnswers>,
 ) -> Result<()> {
     let user_id = query.from.id.0;
     let query_id = query.id.clone();
     let data = query.data.as_deref().unwrap_or_default();
     let message_id = query.message.as_ref().map(|m| m.id().0);
+    let thread_id = query.regular_message().and_then(topic_thread_id);
     let chat_id = query.message.as_ref().map_or(0, |m| m.chat().id.0);
     let trace_id = Uuid::new_v4();
     let span = tracing::info_span!("callback_query_handler", trace_id = %trace_id, user_id = %user_id, chat_id = %chat_id, callback_query_id = %query_id);
@@ -425,14 +411,13 @@ pub async fn callback_query_handler(
 
     tracing::debug!(callback_query_id = %query_id, %user_id, message_id = ?message_id, callback_data = %data, "Received callback query");
 
-    if let Err(e) = bot.answer_callback_query(query.id.clone()).await {
-        tracing::warn!(callback_query_id = %query_id, user_id = %user_id, error = %e, "Failed to answer callback query");
-    }
+    answers.received(&bot, &query_id).await;
 
     let mut incoming_msg = IncomingMessage::new_callback_query(
         chat_id,
         user_id,
         message_id.unwrap_or(0),
+        thread_id,
         data.to_string(),
         query_id.clone(),
         None, // bot_id - could be retrieved from bot.get_me() if needed
@@ -735,3 +720,167 @@ pub async fn edited_message_handler(
     Ok(())
     }.instrument(span).await
 }
+
+pub async fn poll_handler(poll: Poll, producer: Arc<dyn MessageBroker>) -> Result<()> {
+    let trace_id = Uuid::new_v4();
+    let span = tracing::info_span!("poll_handler", trace_id = %trace_id, poll_id = %poll.id);
+
+    async move {
+        let mut incoming_msg = IncomingMessage::new_poll(&poll, None, None);
+        // Override the auto-generated trace_id with our span's trace_id
+        incoming_msg.trace_id = trace_id;
+
+        let json = serde_json::to_string(&incoming_msg).map_err(|e| {
+            tracing::error!(error = %e, "Failed to serialize poll to JSON");
+            e
+        })?;
+
+        // Polls carry no chat or user, so they are partitioned by poll
+        tracing::info!(
+            key = "poll",
+            total_voter_count = poll.total_voter_count,
+            is_closed = poll.is_closed,
+            "Sending poll update to Kafka"
+        );
+
+        producer
+            .publish(Some(&poll.id), json.as_bytes())
+            .await
+            .map_err(|e| {
+                tracing::error!(key = "poll", error = %e, "Failed to send poll update to Kafka");
+                e
+            })?;
+
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
+
+pub async fn poll_answer_handler(
+    answer: PollAnswer,
+    producer: Arc<dyn MessageBroker>,
+    auth: Arc<RwLock<AuthService>>,
+) -> Result<()> {
+    let trace_id = Uuid::new_v4();
+    let span =
+        tracing::info_span!("poll_answer_handler", trace_id = %trace_id, poll_id = %answer.poll_id);
+
+    async move {
+        let user = match &answer.voter {
+            MaybeAnonymousUser::User(user) => Some(user),
+            MaybeAnonymousUser::Chat(_) => None,
+        };
+
+        // Auth gate
+        if let Some(user) = user {
+            let auth_read = auth.read().await;
+            if auth_read.check(user.id.0, user.username.as_deref()).is_none() && !auth_read.is_empty() {
+                tracing::warn!(telegram_user_id = user.id.0, "Unauthorized poll answer — dropping");
+                return Ok(());
+            }
+        }
+
+        let mut incoming_msg = IncomingMessage::new_poll_answer(&answer, None, None);
+        // Override the auto-generated trace_id with our span's trace_id
+        incoming_msg.trace_id = trace_id;
+
+        let json = serde_json::to_string(&incoming_msg).map_err(|e| {
+            tracing::error!(error = %e, "Failed to serialize poll answer to JSON");
+            e
+        })?;
+
+        // Use telegram_user_id as the key for Kafka partitioning
+        let kafka_key = user.map(|user| user.id.0.to_string());
+        tracing::info!(key = "poll_answer", kafka_key = ?kafka_key, option_ids = ?answer.option_ids, "Sending poll answer to Kafka");
+
+        producer
+            .publish(kafka_key.as_deref(), json.as_bytes())
+            .await
+            .map_err(|e| {
+                tracing::error!(key = "poll_answer", error = %e, "Failed to send poll answer to Kafka");
+                e
+            })?;
+
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
+
+/// Publish a request to join a chat, so the backend can approve or decline it.
+///
+/// Join requests and member updates come from users who are usually unknown to
+/// the bot, so they are not subject to the auth gate.
+pub async fn chat_join_request_handler(
+    request: ChatJoinRequest,
+    producer: Arc<dyn MessageBroker>,
+) -> Result<()> {
+    let trace_id = Uuid::new_v4();
+    let span = tracing::info_span!("chat_join_request_handler", trace_id = %trace_id, chat_id = %request.chat.id.0, user_id = %request.from.id.0);
+
+    async move {
+        let mut incoming_msg = IncomingMessage::new_chat_join_request(&request, None, None);
+        // Override the auto-generated trace_id with our span's trace_id
+        incoming_msg.trace_id = trace_id;
+
+        let json = serde_json::to_string(&incoming_msg).map_err(|e| {
+            tracing::error!(error = %e, "Failed to serialize chat join request to JSON");
+            e
+        })?;
+
+        // Use telegram_user_id as the key for Kafka partitioning
+        let kafka_key = request.from.id.0.to_string();
+        tracing::info!(key = "chat_join_request", kafka_key = %kafka_key, "Sending chat join request to Kafka");
+
+        producer
+            .publish(Some(&kafka_key), json.as_bytes())
+            .await
+            .map_err(|e| {
+                tracing::error!(key = "chat_join_request", error = %e, "Failed to send chat join request to Kafka");
+                e
+            })?;
+
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
+
+/// Publish a change of a member's status, e.g. a user joining, leaving or
+/// being banned.
+pub async fn chat_member_handler(
+    update: ChatMemberUpdated,
+    producer: Arc<dyn MessageBroker>,
+) -> Result<()> {
+    let trace_id = Uuid::new_v4();
+    let user_id = update.new_chat_member.user.id.0;
+    let span = tracing::info_span!("chat_member_handler", trace_id = %trace_id, chat_id = %update.chat.id.0, user_id = %user_id);
+
+    async move {
+        let mut incoming_msg = IncomingMessage::new_chat_member(&update, None, None);
+        // Override the auto-generated trace_id with our span's trace_id
+        incoming_msg.trace_id = trace_id;
+
+        let json = serde_json::to_string(&incoming_msg).map_err(|e| {
+            tracing::error!(error = %e, "Failed to serialize chat member update to JSON");
+            e
+        })?;
+
+        // Use telegram_user_id as the key for Kafka partitioning
+        let kafka_key = user_id.to_string();
+        tracing::info!(key = "chat_member", kafka_key = %kafka_key, old_status = ?update.old_chat_member.status(), new_status = ?update.new_chat_member.status(), "Sending chat member update to Kafka");
+
+        producer
+            .publish(Some(&kafka_key), json.as_bytes())
+            .await
+            .map_err(|e| {
+                tracing::error!(key = "chat_member", error = %e, "Failed to send chat member update to Kafka");
+                e
+            })?;
+
+        Ok(())
+    }
+    .instrument(span)
+    .await
+}
@@ -1,13 +1,16 @@
-use crate::kafka_processing::outgoing::{ButtonInfo, ReplyKeyboardMarkup};
+use crate::kafka_processing::outgoing::{
+    ButtonAction, ButtonInfo, ChatPermissionsInfo, ReplyKeyboardMarkup, ReplyMarkupInfo,
+};
 use crate::telegram_handler::incoming::{FileInfo, FileMetadata, FileType};
 use regex::Regex;
 use std::error::Error;
 use teloxide::Bot;
 use teloxide::prelude::Requester;
 use teloxide::types::{
-    Animation, Audio, ButtonRequest, Document, FileMeta, InlineKeyboardButton,
-    InlineKeyboardMarkup, KeyboardButton, KeyboardButtonPollType, KeyboardMarkup, PhotoSize,
-    Sticker, Video, VideoNote, Voice,
+    Animation, Audio, ButtonRequest, ChatPermissions, Document, FileMeta, ForceReply,
+    InlineKeyboardButton, InlineKeyboardButtonKind, InlineKeyboardMarkup, KeyboardButton,
+    KeyboardButtonPollType, KeyboardMarkup, KeyboardRemove, LoginUrl, PhotoSize, ReactionType,
+    ReplyMarkup, Sticker, True, Video, VideoNote, Voice, WebAppInfo,
 };
 
 /// Escapes HTML characters but preserves allowed Telegram HTML tags
@@ -302,89 +305,200 @@ pub fn format_telegram_markdown(text: &str) -> String {
     result
 }
 
-pub fn create_markup(buttons_opt: &Option<Vec<Vec<ButtonInfo>>>) -> Option<InlineKeyboardMarkup> {
-    buttons_opt.as_ref().map(|buttons| {
-        InlineKeyboardMarkup::new(buttons.iter().map(|row| {
-            row.iter().map(|button_info| {
-                InlineKeyboardButton::callback(
-                    button_info.text.clone(),
-                    button_info.callback_data.clone(),
-                )
-            })
-        }))
-    })
+/// Reply markup for a message. teloxide-core 0.11 has no `copy_text` button
+/// kind, so inline keyboards with one are built as raw Bot API JSON instead.
+#[derive(Debug, Clone)]
+pub enum Markup<M> {
+    Typed(M),
+    Raw(serde_json::Value),
 }
 
-pub fn create_reply_keyboard(keyboard_opt: &Option<ReplyKeyboardMarkup>) -> Option<KeyboardMarkup> {
-    keyboard_opt.as_ref().map(|keyboard| {
-        let keyboard_buttons: Vec<Vec<KeyboardButton>> = keyboard
-            .keyboard
-            .iter()
-            .map(|row| {
-                row.iter()
-                    .map(|button| {
-                        let mut kb_button = KeyboardButton::new(button.text.clone());
-
-                        if let Some(true) = button.request_contact {
-                            kb_button = kb_button.request(ButtonRequest::Contact);
-                        }
-
-                        if let Some(true) = button.request_location {
-                            kb_button = kb_button.request(ButtonRequest::Location);
-                        }
-
-                        if let Some(poll) = &button.request_poll {
-                            let poll_type = match poll.poll_type.as_deref() {
-                                Some("quiz") => KeyboardButtonPollType::Quiz,
-                                Some("regular") => KeyboardButtonPollType::Regular,
-                                _ => KeyboardButtonPollType::Regular,
-                            };
-                            kb_button = kb_button.request(ButtonRequest::Poll(poll_type));
-                        }
-
-                        if let Some(_web_app) = &button.web_app {
-                            // Note: WebApp functionality requires additional setup
-                            // For now, we'll skip web app buttons
-                        }
-
-                        kb_button
-                    })
-                    .collect()
-            })
-            .collect();
-
-        let mut reply_keyboard = KeyboardMarkup::new(keyboard_buttons);
-
-        if let Some(resize) = keyboard.resize_keyboard
-            && resize
-        {
-            reply_keyboard = reply_keyboard.resize_keyboard();
+impl<M> Markup<M> {
+    pub fn map<N>(self, f: impl FnOnce(M) -> N) -> Markup<N> {
+        match self {
+            Markup::Typed(markup) => Markup::Typed(f(markup)),
+            Markup::Raw(markup) => Markup::Raw(markup),
         }
+    }
+}
 
-        if let Some(one_time) = keyboard.one_time_keyboard
-            && one_time
-        {
-            reply_keyboard = reply_keyboard.one_time_keyboard();
-        }
+pub fn create_markup(
+    buttons_opt: &Option<Vec<Vec<ButtonInfo>>>,
+) -> Option<Markup<InlineKeyboardMarkup>> {
+    buttons_opt.as_deref().map(inline_keyboard)
+}
 
-        if let Some(persistent) = keyboard.is_persistent
-            && persistent
-        {
-            reply_keyboard = reply_keyboard.persistent();
-        }
+fn inline_keyboard(buttons: &[Vec<ButtonInfo>]) -> Markup<InlineKeyboardMarkup> {
+    let typed = buttons
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|button_info| {
+                    let kind = button_kind(&button_info.action)?;
+                    Some(InlineKeyboardButton::new(button_info.text.clone(), kind))
+                })
+                .collect::<Option<Vec<_>>>()
+        })
+        .collect::<Option<Vec<_>>>();
+    match typed {
+        Some(rows) => Markup::Typed(InlineKeyboardMarkup::new(rows)),
+        None => Markup::Raw(serde_json::json!({
+            "inline_keyboard": buttons
+                .iter()
+                .map(|row| row.iter().map(raw_button).collect::<Vec<_>>())
+                .collect::<Vec<_>>(),
+        })),
+    }
+}
+
+/// A button as Bot API JSON, for keyboards that cannot be typed.
+fn raw_button(button_info: &ButtonInfo) -> serde_json::Value {
+    match (&button_info.action, button_kind(&button_info.action)) {
+        (ButtonAction::CopyText(text), _) => serde_json::json!({
+            "text": button_info.text,
+            "copy_text": { "text": text },
+        }),
+        (_, kind) => serde_json::to_value(InlineKeyboardButton::new(
+            button_info.text.clone(),
+            kind.expect("only copy_text buttons have no typed kind"),
+        ))
+        .expect("inline keyboard buttons serialize to JSON"),
+    }
+}
 
-        if let Some(placeholder) = &keyboard.input_field_placeholder {
-            reply_keyboard = reply_keyboard.input_field_placeholder(placeholder.clone());
+/// The typed kind of a button, or `None` for `copy_text`.
+fn button_kind(action: &ButtonAction) -> Option<InlineKeyboardButtonKind> {
+    let kind = match action {
+        ButtonAction::CallbackData(data) => InlineKeyboardButtonKind::CallbackData(data.clone()),
+        ButtonAction::Url(url) => InlineKeyboardButtonKind::Url(url.clone()),
+        ButtonAction::WebApp(url) => {
+            InlineKeyboardButtonKind::WebApp(WebAppInfo { url: url.clone() })
+        }
+        ButtonAction::LoginUrl {
+            url,
+            forward_text,
+            bot_username,
+            request_write_access,
+        } => InlineKeyboardButtonKind::LoginUrl(LoginUrl {
+            url: url.clone(),
+            forward_text: forward_text.clone(),
+            bot_username: bot_username.clone(),
+            request_write_access: *request_write_access,
+        }),
+        ButtonAction::SwitchInlineQuery(query) => {
+            InlineKeyboardButtonKind::SwitchInlineQuery(query.clone())
         }
+        ButtonAction::SwitchInlineQueryCurrentChat(query) => {
+            InlineKeyboardButtonKind::SwitchInlineQueryCurrentChat(query.clone())
+        }
+        ButtonAction::Pay => InlineKeyboardButtonKind::Pay(True),
+        ButtonAction::CopyText(_) => return None,
+    };
+    Some(kind)
+}
 
-        if let Some(selective) = keyboard.selective
-            && selective
-        {
-            reply_keyboard = reply_keyboard.selective();
+pub fn create_reply_markup(markup: &ReplyMarkupInfo) -> Markup<ReplyMarkup> {
+    let markup = match markup {
+        ReplyMarkupInfo::Inline { buttons } => {
+            return inline_keyboard(buttons).map(ReplyMarkup::InlineKeyboard);
         }
+        ReplyMarkupInfo::Reply(keyboard) => ReplyMarkup::Keyboard(reply_keyboard(keyboard)),
+        ReplyMarkupInfo::Remove { selective } => {
+            let mut remove = KeyboardRemove::new();
+            if *selective == Some(true) {
+                remove = remove.selective();
+            }
+            ReplyMarkup::KeyboardRemove(remove)
+        }
+        ReplyMarkupInfo::ForceReply {
+            input_field_placeholder,
+            selective,
+        } => {
+            let mut force_reply = ForceReply::new();
+            if let Some(placeholder) = input_field_placeholder {
+                force_reply = force_reply.input_field_placeholder(placeholder.clone());
+            }
+            if *selective == Some(true) {
+                force_reply = force_reply.selective();
+            }
+            ReplyMarkup::ForceReply(force_reply)
+        }
+    };
+    Markup::Typed(markup)
+}
 
-        reply_keyboard
-    })
+pub fn create_reply_keyboard(keyboard_opt: &Option<ReplyKeyboardMarkup>) -> Option<KeyboardMarkup> {
+    keyboard_opt.as_ref().map(reply_keyboard)
+}
+
+fn reply_keyboard(keyboard: &ReplyKeyboardMarkup) -> KeyboardMarkup {
+    let keyboard_buttons: Vec<Vec<KeyboardButton>> = keyboard
+        .keyboard
+        .iter()
+        .map(|row| {
+            row.iter()
+                .map(|button| {
+                    let mut kb_button = KeyboardButton::new(button.text.clone());
+
+                    if let Some(true) = button.request_contact {
+                        kb_button = kb_button.request(ButtonRequest::Contact);
+                    }
+
+                    if let Some(true) = button.request_location {
+                        kb_button = kb_button.request(ButtonRequest::Location);
+                    }
+
+                    if let Some(poll) = &button.request_poll {
+                        let poll_type = match poll.poll_type.as_deref() {
+                            Some("quiz") => KeyboardButtonPollType::Quiz,
+                            Some("regular") => KeyboardButtonPollType::Regular,
+                            _ => KeyboardButtonPollType::Regular,
+                        };
+                        kb_button = kb_button.request(ButtonRequest::Poll(poll_type));
+                    }
+
+                    if let Some(_web_app) = &button.web_app {
+                        // Note: WebApp functionality requires additional setup
+                        // For now, we'll skip web app buttons
+                    }
+
+                    kb_button
+                })
+                .collect()
+        })
+        .collect();
+
+    let mut reply_keyboard = KeyboardMarkup::new(keyboard_buttons);
+
+    if let Some(resize) = keyboard.resize_keyboard
+        && resize
+    {
+        reply_keyboard = reply_keyboard.resize_keyboard();
+    }
+
+    if let Some(one_time) = keyboard.one_time_keyboard
+        && one_time
+    {
+        reply_keyboard = reply_keyboard.one_time_keyboard();
+    }
+
+    if let Some(persistent) = keyboard.is_persistent
+        && persistent
+    {
+        reply_keyboard = reply_keyboard.persistent();
+    }
+
+    if let Some(placeholder) = &keyboard.input_field_placeholder {
+        reply_keyboard = reply_keyboard.input_field_placeholder(placeholder.clone());
+    }
+
+    if let Some(selective) = keyboard.selective
+        && selective
+    {
+        reply_keyboard = reply_keyboard.selective();
+    }
+
+    reply_keyboard
 }
 
 pub async fn get_file_info(
@@ -422,6 +536,76 @@ pub async fn get_file_info(
     })
 }
 
+pub fn create_chat_permissions(permissions: &ChatPermissionsInfo) -> ChatPermissions {
+    [
+        (
+            permissions.can_send_messages,
+            ChatPermissions::SEND_MESSAGES,
+        ),
+        (permissions.can_send_audios, ChatPermissions::SEND_AUDIOS),
+        (
+            permissions.can_send_documents,
+            ChatPermissions::SEND_DOCUMENTS,
+        ),
+        (permissions.can_send_photos, ChatPermissions::SEND_PHOTOS),
+        (permissions.can_send_videos, ChatPermissions::SEND_VIDEOS),
+        (
+            permissions.can_send_video_notes,
+            ChatPermissions::SEND_VIDEO_NOTES,
+        ),
+        (
+            permissions.can_send_voice_notes,
+            ChatPermissions::SEND_VOICE_NOTES,
+        ),
+        (permissions.can_send_polls, ChatPermissions::SEND_POLLS),
+        (
+            permissions.can_send_other_messages,
+            ChatPermissions::SEND_OTHER_MESSAGES,
+        ),
+        (
+            permissions.can_add_web_page_previews,
+            ChatPermissions::ADD_WEB_PAGE_PREVIEWS,
+        ),
+        (permissions.can_change_info, ChatPermissions::CHANGE_INFO),
+        (permissions.can_invite_users, ChatPermissions::INVITE_USERS),
+        (permissions.can_pin_messages, ChatPermissions::PIN_MESSAGES),
+        (
+            permissions.can_manage_topics,
+            ChatPermissions::MANAGE_TOPICS,
+        ),
+    ]
+    .into_iter()
+    .filter(|(allowed, _)| *allowed)
+    .fold(ChatPermissions::empty(), |all, (_, permission)| {
+        all | permission
+    })
+}
+
+/// A reaction as the string backends see: the emoji itself, `custom:<id>` for
+/// a custom emoji and `paid` for a paid reaction.
+pub fn reaction_to_string(reaction: &ReactionType) -> String {
+    match reaction {
+        ReactionType::Emoji { emoji } => emoji.clone(),
+        ReactionType::CustomEmoji { custom_emoji_id } => format!("custom:{}", custom_emoji_id),
+        ReactionType::Paid => "paid".to_string(),
+    }
+}
+
+/// The reaction encoded by `reaction_to_string`.
+pub fn reaction_from_string(reaction: &str) -> ReactionType {
+    if reaction == "paid" {
+        return ReactionType::Paid;
+    }
+    match reaction.strip_prefix("custom:") {
+        Some(custom_emoji_id) => ReactionType::CustomEmoji {
+            custom_emoji_id: custom_emoji_id.to_string(),
+        },
+        None => ReactionType::Emoji {
+            emoji: reaction.to_string(),
+        },
+    }
+}
+
 pub fn select_best_photo(photos: &[PhotoSize]) -> Option<&PhotoSize> {
     photos.iter().max_by(|a, b| {
         // Compare by dimensions since file.size is not optional in teloxide
@@ -638,4 +822,20 @@ mod tests {
         // Just test that it doesn't panic and produces some output
         assert!(!result.is_empty());
     }
+
+    #[test]
+    fn test_reaction_string_round_trip() {
+        for reaction in ["👀", "custom:5368324170671202286", "paid"] {
+            assert_eq!(
+                reaction_to_string(&reaction_from_string(reaction)),
+                reaction
+            );
+        }
+        assert_eq!(
+            reaction_from_string("custom:42"),
+            ReactionType::CustomEmoji {
+                custom_emoji_id: "42".to_string()
+            }
+        );
+    }
 }
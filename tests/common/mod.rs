@@ -0,0 +1,361 @@
+//! Minimal stand-in for the Telegram Bot API used by the integration tests.
+//!
+//! Every request is recorded, and answered with a canned successful result so
+//! that handlers and the consumer loop can run without network access.
+//! [`Harness`] wires the mock to an in-memory broker and a running consumer loop.
+
+#![allow(dead_code)]
+
+use ratatoskr::broker::memory::Settlement;
+use ratatoskr::kafka_processing::{OutgoingContext, start_broker_consumer_loop};
+use ratatoskr::{InMemoryBroker, MessageBroker};
+use serde_json::{Value, json};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use teloxide::Bot;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+/// A Bot API call received by the mock server.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    /// Bot API method name, e.g. `sendMessage`
+    pub method: String,
+    /// Raw request body (JSON or multipart)
+    pub body: String,
+}
+
+impl RecordedRequest {
+    /// Parse the body as JSON; panics for multipart requests.
+    pub fn json(&self) -> Value {
+        serde_json::from_str(&self.body).expect("request body is not JSON")
+    }
+}
+
+#[derive(Default)]
+struct State {
+    requests: Mutex<Vec<RecordedRequest>>,
+    next_message_id: AtomicI32,
+    /// Responses to return instead of the canned result, per method
+    scripted: Mutex<HashMap<String, VecDeque<Value>>>,
+}
+
+pub struct MockTelegram {
+    addr: SocketAddr,
+    state: Arc<State>,
+}
+
+impl MockTelegram {
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let state = Arc::new(State {
+            next_message_id: AtomicI32::new(1000),
+            ..Default::default()
+        });
+
+        let server_state = state.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(serve_connection(stream, server_state.clone()));
+            }
+        });
+
+        Self { addr, state }
+    }
+
+    /// A bot whose API calls are routed to this server.
+    pub fn bot(&self) -> Bot {
+        let url = reqwest::Url::parse(&format!("http://{}/", self.addr)).unwrap();
+        Bot::new("123456:TEST").set_api_url(url)
+    }
+
+    /// Answer the next call of `method` with `response` (a full Bot API
+    /// response such as `{"ok": false, "error_code": 429, ...}`).
+    pub fn respond_once(&self, method: &str, response: Value) {
+        self.state
+            .scripted
+            .lock()
+            .unwrap()
+            .entry(method.to_string())
+            .or_default()
+            .push_back(response);
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state.requests.lock().unwrap().clone()
+    }
+
+    pub fn requests_for(&self, method: &str) -> Vec<RecordedRequest> {
+        self.requests()
+            .into_iter()
+            .filter(|r| r.method == method)
+            .collect()
+    }
+
+    /// Wait until `count` calls of `method` were received, panicking after 5 seconds.
+    pub async fn wait_for_n(&self, method: &str, count: usize) -> Vec<RecordedRequest> {
+        let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+        loop {
+            let found = self.requests_for(method);
+            if found.len() >= count {
+                return found;
+            }
+            if tokio::time::Instant::now() > deadline {
+                panic!(
+                    "timed out waiting for {count} {method} request(s), got {:?}",
+                    self.requests()
+                );
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    }
+
+    /// Wait for the first call of `method`.
+    pub async fn wait_for(&self, method: &str) -> RecordedRequest {
+        self.wait_for_n(method, 1).await.remove(0)
+    }
+}
+
+/// A consumer loop delivering outgoing messages from an in-memory broker to
+/// a mock Telegram server.
+pub struct Harness {
+    pub telegram: MockTelegram,
+    pub broker: Arc<InMemoryBroker>,
+}
+
+impl Harness {
+    /// Start the consumer loop with the default outgoing context.
+    pub async fn start() -> Self {
+        Self::start_with(|ctx| ctx).await
+    }
+
+    /// Start the consumer loop with the outgoing context returned by `configure`.
+    pub async fn start_with(configure: impl FnOnce(OutgoingContext) -> OutgoingContext) -> Self {
+        let telegram = MockTelegram::start().await;
+        let broker = Arc::new(InMemoryBroker::default());
+        tokio::spawn(start_broker_consumer_loop(
+            configure(OutgoingContext::new(telegram.bot())),
+            broker.clone() as Arc<dyn MessageBroker>,
+        ));
+        Self { telegram, broker }
+    }
+
+    /// Queue an outgoing message of `message_type` for the private chat 42.
+    pub async fn send(&self, message_type: Value) {
+        self.send_to(42, None, message_type).await;
+    }
+
+    /// Queue an outgoing message of `message_type` for a chat and thread.
+    pub async fn send_to(&self, chat_id: i64, thread_id: Option<i32>, message_type: Value) {
+        let outgoing = json!({
+            "message_type": message_type,
+            "timestamp": "2024-01-01T00:00:00Z",
+            "target": { "platform": "telegram", "chat_id": chat_id, "thread_id": thread_id }
+        });
+        self.broker
+            .send_outgoing(outgoing.to_string())
+            .await
+            .unwrap();
+    }
+
+    /// Wait until the broker has settled `count` outgoing messages.
+    pub async fn wait_for_settlements(&self, count: usize) -> Vec<Settlement> {
+        wait_for_settlements(&self.broker, count).await
+    }
+}
+
+/// Wait until `broker` has settled `count` outgoing messages.
+pub async fn wait_for_settlements(broker: &InMemoryBroker, count: usize) -> Vec<Settlement> {
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    loop {
+        let settlements = broker.settlements();
+        if settlements.len() >= count {
+            return settlements;
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "timed out waiting for {count} settlement(s), got {settlements:?}"
+        );
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+async fn serve_connection(stream: TcpStream, state: Arc<State>) {
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        match reader.read_line(&mut request_line).await {
+            Ok(0) | Err(_) => return,
+            Ok(_) => {}
+        }
+        let path = request_line
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+
+        let mut content_length = 0;
+        let mut chunked = false;
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).await.unwrap_or(0) == 0 {
+                return;
+            }
+            let header = header.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = header.split_once(':') {
+                let value = value.trim();
+                match name.to_ascii_lowercase().as_str() {
+                    "content-length" => content_length = value.parse().unwrap_or(0),
+                    "transfer-encoding" => chunked = value.eq_ignore_ascii_case("chunked"),
+                    _ => {}
+                }
+            }
+        }
+
+        let body = if chunked {
+            read_chunked(&mut reader).await
+        } else {
+            let mut body = vec![0; content_length];
+            if reader.read_exact(&mut body).await.is_err() {
+                return;
+            }
+            body
+        };
+
+        // Method names are case-insensitive; teloxide sends `SendMessage`.
+        let method = path.rsplit('/').next().unwrap_or_default();
+        let mut chars = method.chars();
+        let method = chars
+            .next()
+            .map(|first| first.to_ascii_lowercase().to_string() + chars.as_str())
+            .unwrap_or_default();
+        let body = String::from_utf8_lossy(&body).into_owned();
+        let scripted = state
+            .scripted
+            .lock()
+            .unwrap()
+            .get_mut(&method)
+            .and_then(VecDeque::pop_front);
+        let payload = scripted
+            .unwrap_or_else(|| json!({ "ok": true, "result": result_for(&method, &body, &state) }))
+            .to_string();
+        state
+            .requests
+            .lock()
+            .unwrap()
+            .push(RecordedRequest { method, body });
+
+        let response = format!(
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            payload.len(),
+            payload
+        );
+        if reader
+            .get_mut()
+            .write_all(response.as_bytes())
+            .await
+            .is_err()
+        {
+            return;
+        }
+    }
+}
+
+async fn read_chunked(reader: &mut BufReader<TcpStream>) -> Vec<u8> {
+    let mut body = Vec::new();
+    loop {
+        let mut size_line = String::new();
+        if reader.read_line(&mut size_line).await.unwrap_or(0) == 0 {
+            return body;
+        }
+        let size = usize::from_str_radix(size_line.trim(), 16).unwrap_or(0);
+        let mut chunk = vec![0; size + 2]; // chunk data + CRLF
+        if reader.read_exact(&mut chunk).await.is_err() {
+            return body;
+        }
+        if size == 0 {
+            return body;
+        }
+        body.extend_from_slice(&chunk[..size]);
+    }
+}
+
+/// Canned `result` for a Bot API method.
+fn result_for(method: &str, body: &str, state: &State) -> Value {
+    let chat_id = serde_json::from_str::<Value>(body)
+        .ok()
+        .and_then(|v| v.get("chat_id").and_then(Value::as_i64))
+        .unwrap_or(1);
+
+    let message = || {
+        json!({
+            "message_id": state.next_message_id.fetch_add(1, Ordering::SeqCst),
+            "date": 1_700_000_000,
+            "chat": { "id": chat_id, "type": "private", "first_name": "Test" },
+            "text": "ok"
+        })
+    };
+
+    match method {
+        "sendChatAction" => json!(true),
+        // One message per album item; each item carries a `"media":"..."` reference.
+        "sendMediaGroup" => {
+            let items = body.matches(r#""media":""#).count().max(1);
+            Value::Array((0..items).map(|_| message()).collect())
+        }
+        "sendPoll" => {
+            let mut sent = message();
+            sent["poll"] = poll(false);
+            sent
+        }
+        "stopPoll" => poll(true),
+        "copyMessage" => {
+            json!({ "message_id": state.next_message_id.fetch_add(1, Ordering::SeqCst) })
+        }
+        // One new ID per copied message.
+        "copyMessages" => {
+            let count = serde_json::from_str::<Value>(body)
+                .ok()
+                .and_then(|v| v.get("message_ids").and_then(Value::as_array).map(Vec::len))
+                .unwrap_or(1);
+            (0..count)
+                .map(|_| json!({ "message_id": state.next_message_id.fetch_add(1, Ordering::SeqCst) }))
+                .collect()
+        }
+        "forwardMessage" => message(),
+        m if m.starts_with("send") || m.starts_with("edit") => message(),
+        _ => json!(true),
+    }
+}
+
+/// The poll every `sendPoll` and `stopPoll` call returns.
+fn poll(is_closed: bool) -> Value {
+    json!({
+        "id": "5000",
+        "question": "?",
+        "options": [],
+        "total_voter_count": 0,
+        "is_closed": is_closed,
+        "is_anonymous": false,
+        "type": "regular",
+        "allows_multiple_answers": false
+    })
+}
+
+/// A private-chat text message from user 42 as Telegram would deliver it.
+pub fn text_message_json(text: &str) -> Value {
+    json!({
+        "message_id": 7,
+        "date": 1_700_000_000,
+        "chat": { "id": 42, "type": "private", "first_name": "Alice", "username": "alice" },
+        "from": { "id": 42, "is_bot": false, "first_name": "Alice", "username": "alice" },
+        "text": text
+    })
+}
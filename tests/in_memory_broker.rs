@@ -0,0 +1,1370 @@
+mod common;
+
+use async_trait::async_trait;
+use common::{Harness, MockTelegram, text_message_json};
+use ratatoskr::auth::AuthService;
+use ratatoskr::broker::memory::Settlement;
+use ratatoskr::broker::{BoxStream, Delivery};
+use ratatoskr::config::UsersConfig;
+use ratatoskr::kafka_processing::callback::{AutoAnswer, CallbackAnswers, CallbackOptions};
+use ratatoskr::kafka_processing::dead_letter::{DeadLetterRecord, ErrorKind};
+use ratatoskr::kafka_processing::dispatch::DispatchOptions;
+use ratatoskr::kafka_processing::rate_limit::{RateLimitMetrics, RateLimitOptions};
+use ratatoskr::kafka_processing::receipt::{DeliveryReceipt, DeliveryStatus};
+use ratatoskr::kafka_processing::stream::StreamOptions;
+use ratatoskr::kafka_processing::{OutgoingContext, start_broker_consumer_loop};
+use ratatoskr::telegram_handler::incoming::{IncomingMessage, IncomingMessageType};
+use ratatoskr::telegram_handler::{
+    callback_query_handler, chat_join_request_handler, chat_member_handler, message_handler,
+    poll_answer_handler,
+};
+use ratatoskr::{InMemoryBroker, MessageBroker};
+use serde_json::json;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use teloxide::types::{CallbackQuery, ChatJoinRequest, ChatMemberUpdated, Message, PollAnswer};
+use tokio::sync::RwLock;
+
+fn callback_query(id: &str) -> CallbackQuery {
+    serde_json::from_value(json!({
+        "id": id,
+        "from": { "id": 42, "is_bot": false, "first_name": "Alice" },
+        "chat_instance": "instance",
+        "data": "action_1",
+        "message": text_message_json("pick one")
+    }))
+    .unwrap()
+}
+
+fn open_auth() -> Arc<RwLock<AuthService>> {
+    Arc::new(RwLock::new(AuthService::new(
+        UsersConfig::default(),
+        PathBuf::from("/nonexistent/users.toml"),
+    )))
+}
+
+#[tokio::test]
+async fn message_handler_publishes_incoming_message() {
+    let telegram = MockTelegram::start().await;
+    let broker = Arc::new(InMemoryBroker::default());
+    let mut incoming = broker.incoming();
+
+    let msg: Message = serde_json::from_value(text_message_json("hello bot")).unwrap();
+    message_handler(telegram.bot(), msg, broker.clone(), open_auth())
+        .await
+        .unwrap();
+
+    let published = incoming.recv().await.unwrap();
+    assert_eq!(published.key.as_deref(), Some("42"));
+    let incoming: IncomingMessage = serde_json::from_slice(&published.payload).unwrap();
+    match incoming.message_type {
+        IncomingMessageType::TelegramMessage(data) => {
+            assert_eq!(data.message.text(), Some("hello bot"));
+            assert!(data.file_attachments.is_empty());
+        }
+        other => panic!("expected TelegramMessage, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn callback_query_handler_answers_and_publishes() {
+    let telegram = MockTelegram::start().await;
+    let broker = Arc::new(InMemoryBroker::default());
+    let mut incoming = broker.incoming();
+
+    callback_query_handler(
+        telegram.bot(),
+        callback_query("cbq-1"),
+        broker.clone(),
+        open_auth(),
+        Arc::new(CallbackAnswers::new(CallbackOptions {
+            auto_answer: AutoAnswer::Immediate,
+            ..Default::default()
+        })),
+    )
+    .await
+    .unwrap();
+
+    let answer = telegram.wait_for("answerCallbackQuery").await;
+    assert_eq!(answer.json()["callback_query_id"], "cbq-1");
+
+    let published = incoming.recv().await.unwrap();
+    let incoming: IncomingMessage = serde_json::from_slice(&published.payload).unwrap();
+    match incoming.message_type {
+        IncomingMessageType::CallbackQuery(data) => {
+            assert_eq!(data.chat_id, 42);
+            assert_eq!(data.message_id, 7);
+            assert_eq!(data.callback_data, "action_1");
+        }
+        other => panic!("expected CallbackQuery, got {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn consumer_loop_delivers_outgoing_messages() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    broker.send_outgoing(b"not json".to_vec()).await.unwrap();
+    broker
+        .send_outgoing(include_str!("data/outgoing_text.json"))
+        .await
+        .unwrap();
+
+    let sent = telegram.wait_for("sendMessage").await.json();
+    assert_eq!(sent["chat_id"], 123456789);
+    assert_eq!(sent["text"], "hello from pipe");
+
+    harness.wait_for_settlements(2).await;
+    let receipts = broker.receipts();
+    assert_eq!(receipts.len(), 1);
+    assert_eq!(receipts[0].key.as_deref(), Some("123456789"));
+    let receipt: DeliveryReceipt = serde_json::from_slice(&receipts[0].payload).unwrap();
+    assert_eq!(receipt.trace_id, uuid::Uuid::from_u128(1));
+    assert_eq!(receipt.status, DeliveryStatus::Delivered);
+    assert_eq!(receipt.message_ids, vec![1000]);
+    assert_eq!(receipt.error, None);
+}
+
+#[tokio::test]
+async fn consumer_loop_dead_letters_failed_messages() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    let missing_image = json!({
+        "trace_id": "00000000-0000-0000-0000-000000000002",
+        "message_type": {
+            "type": "ImageMessage",
+            "data": { "image_path": "/nonexistent/image.jpg", "caption": null }
+        },
+        "timestamp": "2024-01-01T00:00:00Z",
+        "target": { "platform": "telegram", "chat_id": 123456789, "thread_id": null }
+    })
+    .to_string();
+    let text = include_str!("data/outgoing_text.json");
+    broker.send_outgoing(b"not json".to_vec()).await.unwrap();
+    broker.send_outgoing(missing_image.clone()).await.unwrap();
+    broker.send_outgoing(text).await.unwrap();
+
+    // Failed messages are acked once they are safely in the dead-letter queue.
+    let settlements = harness.wait_for_settlements(3).await;
+    assert_eq!(
+        settlements,
+        vec![
+            Settlement::Acked(b"not json".to_vec()),
+            Settlement::Acked(missing_image.clone().into_bytes()),
+            Settlement::Acked(text.as_bytes().to_vec()),
+        ]
+    );
+    assert!(telegram.requests_for("sendPhoto").is_empty());
+
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 2);
+
+    let malformed: DeadLetterRecord = serde_json::from_slice(&dead_letters[0].payload).unwrap();
+    assert_eq!(dead_letters[0].key, None);
+    assert_eq!(malformed.error_kind, ErrorKind::MalformedPayload);
+    assert_eq!(malformed.original_payload, "not json");
+
+    let undeliverable: DeadLetterRecord = serde_json::from_slice(&dead_letters[1].payload).unwrap();
+    assert_eq!(dead_letters[1].key.as_deref(), Some("123456789"));
+    assert_eq!(undeliverable.error_kind, ErrorKind::InvalidMessage);
+    assert_eq!(undeliverable.trace_id, Some(uuid::Uuid::from_u128(2)));
+    assert_eq!(undeliverable.attempts, 1);
+    assert!(undeliverable.error_message.contains("Image file not found"));
+    assert_eq!(undeliverable.original_payload, missing_image);
+
+    // Only messages that could be parsed get a receipt.
+    let receipts = broker.receipts();
+    assert_eq!(receipts.len(), 2);
+    let failed: DeliveryReceipt = serde_json::from_slice(&receipts[0].payload).unwrap();
+    assert_eq!(failed.trace_id, uuid::Uuid::from_u128(2));
+    assert_eq!(failed.status, DeliveryStatus::Failed);
+    assert!(failed.message_ids.is_empty());
+    assert!(failed.error.unwrap().contains("Image file not found"));
+}
+
+#[tokio::test]
+async fn consumer_loop_dead_letters_nil_trace_ids_and_keeps_going() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    let nil_trace_id = json!({
+        "trace_id": "00000000-0000-0000-0000-000000000000",
+        "message_type": { "type": "TextMessage", "data": { "text": "lost" } },
+        "timestamp": "2024-01-01T00:00:00Z",
+        "target": { "platform": "telegram", "chat_id": 42, "thread_id": null }
+    })
+    .to_string();
+    broker.send_outgoing(nil_trace_id.clone()).await.unwrap();
+    harness
+        .send(json!({ "type": "TextMessage", "data": { "text": "delivered" } }))
+        .await;
+
+    let settlements = harness.wait_for_settlements(2).await;
+    assert!(
+        settlements
+            .iter()
+            .all(|s| matches!(s, Settlement::Acked(_)))
+    );
+    let sent = telegram.requests_for("sendMessage");
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].json()["text"], "delivered");
+
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    let record: DeadLetterRecord = serde_json::from_slice(&dead_letters[0].payload).unwrap();
+    assert_eq!(record.error_kind, ErrorKind::MalformedPayload);
+    assert_eq!(record.original_payload, nil_trace_id);
+}
+
+/// Fails the first `failures` dead-letter publishes and passes everything
+/// else on to `inner`.
+struct FlakyDeadLetters {
+    inner: Arc<InMemoryBroker>,
+    failures: AtomicUsize,
+}
+
+#[async_trait]
+impl MessageBroker for FlakyDeadLetters {
+    async fn publish(&self, key: Option<&str>, payload: &[u8]) -> anyhow::Result<()> {
+        self.inner.publish(key, payload).await
+    }
+
+    async fn publish_dead_letter(&self, key: Option<&str>, payload: &[u8]) -> anyhow::Result<()> {
+        if self
+            .failures
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1))
+            .is_ok()
+        {
+            anyhow::bail!("dead-letter queue unavailable");
+        }
+        self.inner.publish_dead_letter(key, payload).await
+    }
+
+    async fn publish_receipt(&self, key: Option<&str>, payload: &[u8]) -> anyhow::Result<()> {
+        self.inner.publish_receipt(key, payload).await
+    }
+
+    async fn publish_metrics(&self, payload: &[u8]) -> anyhow::Result<()> {
+        self.inner.publish_metrics(payload).await
+    }
+
+    async fn subscribe<'a>(&'a self) -> anyhow::Result<BoxStream<'a, Delivery>> {
+        self.inner.subscribe().await
+    }
+}
+
+#[tokio::test]
+async fn dead_letters_are_retried_in_order_and_then_nacked() {
+    let telegram = MockTelegram::start().await;
+    let broker = Arc::new(InMemoryBroker::default());
+    // Five failed attempts for the first message, two for the second.
+    let flaky = Arc::new(FlakyDeadLetters {
+        inner: broker.clone(),
+        failures: AtomicUsize::new(7),
+    });
+    tokio::spawn(start_broker_consumer_loop(
+        OutgoingContext::new(telegram.bot()),
+        flaky.clone() as Arc<dyn MessageBroker>,
+    ));
+    tokio::time::pause();
+
+    let missing_image = |trace_id: u128| {
+        json!({
+            "trace_id": uuid::Uuid::from_u128(trace_id),
+            "message_type": {
+                "type": "ImageMessage",
+                "data": { "image_path": "/nonexistent/image.jpg" }
+            },
+            "timestamp": "2024-01-01T00:00:00Z",
+            "target": { "platform": "telegram", "chat_id": 42 }
+        })
+        .to_string()
+    };
+    let text = include_str!("data/outgoing_text.json").replace("123456789", "42");
+    for payload in [missing_image(1), missing_image(2), text.clone()] {
+        broker.send_outgoing(payload).await.unwrap();
+    }
+
+    // Retrying holds the chat, so the reply is only sent after both failures settled.
+    tokio::time::timeout(Duration::from_secs(120), async {
+        while broker.settlements().len() < 3 {
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    })
+    .await
+    .expect("timed out waiting for 3 settlements");
+    assert_eq!(
+        broker.settlements(),
+        vec![
+            Settlement::Nacked(missing_image(1).into_bytes()),
+            Settlement::Acked(missing_image(2).into_bytes()),
+            Settlement::Acked(text.into_bytes()),
+        ]
+    );
+    assert_eq!(flaky.failures.load(Ordering::SeqCst), 0);
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    let record: DeadLetterRecord = serde_json::from_slice(&dead_letters[0].payload).unwrap();
+    assert_eq!(record.trace_id, Some(uuid::Uuid::from_u128(2)));
+    assert_eq!(telegram.requests_for("sendMessage").len(), 1);
+}
+
+#[tokio::test]
+async fn consumer_loop_retries_flood_control_but_not_client_errors() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    telegram.respond_once(
+        "sendMessage",
+        json!({
+            "ok": false,
+            "error_code": 429,
+            "description": "Too Many Requests: retry after 1",
+            "parameters": { "retry_after": 1 }
+        }),
+    );
+    broker
+        .send_outgoing(include_str!("data/outgoing_text.json"))
+        .await
+        .unwrap();
+    harness.wait_for_settlements(1).await;
+    assert_eq!(telegram.requests_for("sendMessage").len(), 2);
+    assert!(broker.dead_letters().is_empty());
+
+    telegram.respond_once(
+        "sendMessage",
+        json!({ "ok": false, "error_code": 400, "description": "Bad Request: chat not found" }),
+    );
+    broker
+        .send_outgoing(include_str!("data/outgoing_text.json"))
+        .await
+        .unwrap();
+    harness.wait_for_settlements(2).await;
+    assert_eq!(telegram.requests_for("sendMessage").len(), 3);
+
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    let record: DeadLetterRecord = serde_json::from_slice(&dead_letters[0].payload).unwrap();
+    assert_eq!(record.error_kind, ErrorKind::TelegramApi);
+    assert_eq!(record.attempts, 1);
+}
+
+#[tokio::test]
+async fn formatting_falls_back_to_plain_text_only_on_entity_errors() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    telegram.respond_once(
+        "sendMessage",
+        json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: can't parse entities: Unsupported start tag \"x\" at byte offset 0"
+        }),
+    );
+    let formatted = json!({ "type": "TextMessage", "data": {
+        "text": "**bold**", "parse_mode": "Markdown"
+    } });
+    harness.send(formatted.clone()).await;
+    harness.wait_for_settlements(1).await;
+    let sent = telegram.requests_for("sendMessage");
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[0].json()["parse_mode"], "HTML");
+    assert_eq!(sent[1].json()["text"], "bold");
+    assert!(sent[1].json().get("parse_mode").is_none());
+    assert!(broker.dead_letters().is_empty());
+
+    telegram.respond_once(
+        "sendMessage",
+        json!({ "ok": false, "error_code": 400, "description": "Bad Request: chat not found" }),
+    );
+    harness.send(formatted).await;
+    harness.wait_for_settlements(2).await;
+    assert_eq!(telegram.requests_for("sendMessage").len(), 3);
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    let record: DeadLetterRecord = serde_json::from_slice(&dead_letters[0].payload).unwrap();
+    assert_eq!(record.error_kind, ErrorKind::TelegramApi);
+    assert!(record.error_message.contains("chat not found"));
+
+    // Media captions fall back the same way.
+    telegram.respond_once(
+        "sendDocument",
+        json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: can't parse entities: Unsupported start tag \"x\" at byte offset 0"
+        }),
+    );
+    harness
+        .send(json!({ "type": "DocumentMessage", "data": {
+            "document": { "type": "url", "url": "https://example.com/report.pdf" },
+            "caption": "**report**"
+        } }))
+        .await;
+    harness.wait_for_settlements(3).await;
+    let documents = telegram.requests_for("sendDocument");
+    assert_eq!(documents.len(), 2);
+    assert!(documents[0].body.contains("<b>report</b>"));
+    assert!(documents[0].body.contains("HTML"));
+    assert!(!documents[1].body.contains("<b>"));
+    assert!(!documents[1].body.contains("HTML"));
+    assert!(documents[1].body.contains("report"));
+    assert_eq!(broker.dead_letters().len(), 1);
+}
+
+#[tokio::test]
+async fn a_throttled_group_does_not_delay_private_chats() {
+    let harness = Harness::start_with(|ctx| {
+        ctx.with_dispatch(DispatchOptions {
+            concurrency: 1,
+            ..DispatchOptions::default()
+        })
+        .with_rate_limits(RateLimitOptions {
+            group_per_minute: 6.0,
+            group_burst: 1,
+            ..RateLimitOptions::default()
+        })
+    })
+    .await;
+    let telegram = &harness.telegram;
+
+    for text in ["first", "second"] {
+        harness
+            .send_to(
+                -100300,
+                None,
+                json!({ "type": "TextMessage", "data": { "text": text } }),
+            )
+            .await;
+    }
+    // The second group message now waits ten seconds for its token, without
+    // holding the only worker.
+    telegram.wait_for("sendMessage").await;
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    harness
+        .send(json!({ "type": "TextMessage", "data": { "text": "private" } }))
+        .await;
+
+    let sent = telegram.wait_for_n("sendMessage", 2).await;
+    let texts: Vec<_> = sent.iter().map(|r| r.json()["text"].clone()).collect();
+    assert_eq!(texts, vec![json!("first"), json!("private")]);
+}
+
+#[tokio::test]
+async fn rate_limit_queue_depth_is_published_as_metrics() {
+    let harness = Harness::start_with(|ctx| {
+        ctx.with_rate_limits(RateLimitOptions {
+            global_per_second: 0.1,
+            global_burst: 1,
+            metrics_interval_secs: 1,
+            ..RateLimitOptions::default()
+        })
+    })
+    .await;
+    let Harness { telegram, broker } = &harness;
+
+    // One message gets the only global token, the other two wait for it.
+    for chat_id in [1, 2, 3] {
+        harness
+            .send_to(
+                chat_id,
+                None,
+                json!({ "type": "TextMessage", "data": { "text": "hi" } }),
+            )
+            .await;
+    }
+    telegram.wait_for("sendMessage").await;
+
+    let deadline = tokio::time::Instant::now() + Duration::from_secs(5);
+    let metrics = loop {
+        if let Some(published) = broker.metrics().last() {
+            let metrics: RateLimitMetrics = serde_json::from_slice(&published.payload).unwrap();
+            if metrics.queue_depth > 0 {
+                break metrics;
+            }
+        }
+        assert!(
+            tokio::time::Instant::now() < deadline,
+            "no queue depth published"
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    };
+    assert_eq!(metrics.queue_depth, 2);
+    assert_eq!(telegram.requests_for("sendMessage").len(), 1);
+}
+
+#[tokio::test]
+async fn forum_topics_are_captured_and_honored() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+    let mut incoming = broker.incoming();
+
+    let mut topic_message = text_message_json("question in a topic");
+    topic_message["chat"] =
+        json!({ "id": -100123, "type": "supergroup", "title": "Forum", "is_forum": true });
+    topic_message["message_thread_id"] = json!(55);
+    topic_message["is_topic_message"] = json!(true);
+    let msg: Message = serde_json::from_value(topic_message).unwrap();
+    message_handler(telegram.bot(), msg, broker.clone(), open_auth())
+        .await
+        .unwrap();
+
+    let published = incoming.recv().await.unwrap();
+    let incoming: IncomingMessage = serde_json::from_slice(&published.payload).unwrap();
+    let IncomingMessageType::TelegramMessage(data) = incoming.message_type else {
+        panic!("expected TelegramMessage");
+    };
+    assert_eq!(data.thread_id, Some(55));
+
+    for message_type in [
+        json!({ "type": "TypingMessage", "data": {} }),
+        json!({ "type": "TextMessage", "data": { "text": "answer in the topic" } }),
+    ] {
+        harness.send_to(-100123, data.thread_id, message_type).await;
+    }
+
+    let action = telegram.wait_for("sendChatAction").await.json();
+    assert_eq!(action["message_thread_id"], 55);
+    let sent = telegram.wait_for("sendMessage").await.json();
+    assert_eq!(sent["chat_id"], -100123);
+    assert_eq!(sent["message_thread_id"], 55);
+}
+
+#[tokio::test]
+async fn media_groups_are_sent_as_one_album() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    let dir = tempfile::tempdir().unwrap();
+    let first = dir.path().join("first.jpg");
+    let second = dir.path().join("second.jpg");
+    std::fs::write(&first, b"first").unwrap();
+    std::fs::write(&second, b"second").unwrap();
+
+    harness
+        .send(json!({
+            "type": "MediaGroup",
+            "data": {
+                "media": [
+                    { "type": "photo", "path": first, "caption": "**first**", "parse_mode": "Markdown" },
+                    { "type": "photo", "path": second, "caption": "second" }
+                ]
+            }
+        }))
+        .await;
+
+    let sent = telegram.wait_for("sendMediaGroup").await;
+    assert!(sent.body.contains("<b>first</b>"));
+    assert!(sent.body.contains(r#""caption":"second""#));
+
+    harness.wait_for_settlements(1).await;
+    let receipt: DeliveryReceipt = serde_json::from_slice(&broker.receipts()[0].payload).unwrap();
+    assert_eq!(receipt.status, DeliveryStatus::Delivered);
+    assert_eq!(receipt.message_ids, vec![1000, 1001]);
+}
+
+#[tokio::test]
+async fn media_can_be_sent_by_file_id_and_url() {
+    let harness = Harness::start().await;
+    let telegram = &harness.telegram;
+
+    for message_type in [
+        json!({ "type": "ImageMessage", "data": { "image": { "type": "file_id", "file_id": "AgACAgIAAxkBAAIC" } } }),
+        json!({ "type": "DocumentMessage", "data": { "document": { "type": "url", "url": "https://example.com/report.pdf" } } }),
+    ] {
+        harness.send(message_type).await;
+    }
+
+    let photo = telegram.wait_for("sendPhoto").await;
+    assert!(photo.body.contains("AgACAgIAAxkBAAIC"));
+    let document = telegram.wait_for("sendDocument").await;
+    assert!(document.body.contains("https://example.com/report.pdf"));
+}
+
+#[tokio::test]
+async fn locations_venues_contacts_and_dice_are_sent() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    for message_type in [
+        json!({ "type": "LocationMessage", "data": { "latitude": 59.91, "longitude": 10.75, "live_period": 900 } }),
+        json!({ "type": "VenueMessage", "data": { "latitude": 59.91, "longitude": 10.75, "title": "Café", "address": "Karl Johans gate 1" } }),
+        json!({ "type": "ContactMessage", "data": { "phone_number": "+4712345678", "first_name": "Ratatoskr" } }),
+        json!({ "type": "DiceMessage", "data": { "emoji": "🎯" } }),
+        json!({ "type": "DiceMessage", "data": { "emoji": "🍕" } }),
+    ] {
+        harness.send(message_type).await;
+    }
+
+    let location = telegram.wait_for("sendLocation").await.json();
+    assert_eq!(location["live_period"], 900);
+    let venue = telegram.wait_for("sendVenue").await.json();
+    assert_eq!(venue["title"], "Café");
+    let contact = telegram.wait_for("sendContact").await.json();
+    assert_eq!(contact["phone_number"], "+4712345678");
+    let dice = telegram.wait_for("sendDice").await.json();
+    assert_eq!(dice["emoji"], "🎯");
+
+    harness.wait_for_settlements(5).await;
+    assert_eq!(telegram.requests_for("sendDice").len(), 1);
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    let record: DeadLetterRecord = serde_json::from_slice(&dead_letters[0].payload).unwrap();
+    assert_eq!(record.error_message, "Unsupported dice emoji: 🍕");
+}
+
+#[tokio::test]
+async fn polls_are_sent_stopped_and_answers_published() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+    let mut incoming = broker.incoming();
+
+    for message_type in [
+        json!({ "type": "PollMessage", "data": {
+            "question": "2 + 2?",
+            "options": ["3", "4"],
+            "type": "quiz",
+            "correct_option_id": 1,
+            "is_anonymous": false
+        } }),
+        json!({ "type": "StopPoll", "data": { "message_id": 1000 } }),
+    ] {
+        harness.send(message_type).await;
+    }
+
+    let poll = telegram.wait_for("sendPoll").await.json();
+    assert_eq!(poll["type"], "quiz");
+    assert_eq!(poll["options"][1]["text"], "4");
+    assert_eq!(poll["correct_option_id"], 1);
+    let stop = telegram.wait_for("stopPoll").await.json();
+    assert_eq!(stop["message_id"], 1000);
+
+    // The receipt links the poll to the message it was sent in.
+    harness.wait_for_settlements(2).await;
+    let sent: DeliveryReceipt = serde_json::from_slice(&broker.receipts()[0].payload).unwrap();
+    assert_eq!(sent.message_ids, vec![1000]);
+    assert_eq!(sent.poll_id.as_deref(), Some("5000"));
+
+    let answer: PollAnswer = serde_json::from_value(json!({
+        "poll_id": "5000",
+        "user": { "id": 42, "is_bot": false, "first_name": "Alice" },
+        "option_ids": [1]
+    }))
+    .unwrap();
+    poll_answer_handler(answer, broker.clone(), open_auth())
+        .await
+        .unwrap();
+
+    let published = incoming.recv().await.unwrap();
+    assert_eq!(published.key.as_deref(), Some("42"));
+    let incoming: IncomingMessage = serde_json::from_slice(&published.payload).unwrap();
+    let IncomingMessageType::PollAnswer(data) = incoming.message_type else {
+        panic!("expected PollAnswer");
+    };
+    assert_eq!(Some(data.poll_id), sent.poll_id);
+    assert_eq!(data.user_id, Some(42));
+    assert_eq!(data.option_ids, vec![1]);
+}
+
+#[tokio::test]
+async fn streamed_messages_are_edited_and_overflow_into_new_messages() {
+    let harness = Harness::start_with(|ctx| {
+        ctx.with_rate_limits(RateLimitOptions {
+            enabled: false,
+            ..RateLimitOptions::default()
+        })
+        .with_streaming(StreamOptions {
+            edit_interval_ms: 200,
+            ..StreamOptions::default()
+        })
+    })
+    .await;
+    let Harness { telegram, broker } = &harness;
+    let send_stream =
+        |data: serde_json::Value| harness.send(json!({ "type": "StreamMessage", "data": data }));
+
+    send_stream(json!({
+        "stream_id": "answer-1",
+        "action": "start",
+        "text": "**Hello**",
+        "parse_mode": "Markdown"
+    }))
+    .await;
+    send_stream(json!({ "stream_id": "answer-1", "action": "append", "text": " wor" })).await;
+    send_stream(json!({ "stream_id": "answer-1", "action": "append", "text": "ld" })).await;
+
+    let first = telegram.wait_for("sendMessage").await.json();
+    assert_eq!(first["text"], "<b>Hello</b>");
+    assert_eq!(first["parse_mode"], "HTML");
+    // Both appends are coalesced into a single edit.
+    let edit = telegram.wait_for("editMessageText").await.json();
+    assert_eq!(edit["message_id"], 1000);
+    assert_eq!(edit["text"], "<b>Hello</b> world");
+    harness.wait_for_settlements(3).await;
+    assert_eq!(telegram.requests_for("editMessageText").len(), 1);
+
+    // Past the edit interval, the next append is flushed right away.
+    tokio::time::sleep(Duration::from_millis(300)).await;
+    let long = "a".repeat(4100);
+    send_stream(json!({ "stream_id": "answer-1", "action": "append", "text": long })).await;
+    send_stream(json!({
+        "stream_id": "answer-1",
+        "action": "finish",
+        "text": "!",
+        "buttons": [[{ "text": "Thanks", "callback_data": "thanks" }]]
+    }))
+    .await;
+
+    let settlements = harness.wait_for_settlements(5).await;
+    assert!(
+        settlements
+            .iter()
+            .all(|s| matches!(s, Settlement::Acked(_)))
+    );
+    let sent = telegram.requests_for("sendMessage");
+    assert_eq!(sent.len(), 2);
+    assert_eq!(sent[1].json()["text"], "a".repeat(19));
+    let edits = telegram.requests_for("editMessageText");
+    assert_eq!(
+        edits.last().unwrap().json()["text"],
+        format!("{}!", "a".repeat(19))
+    );
+    let markup = telegram.wait_for("editMessageReplyMarkup").await.json();
+    assert_eq!(
+        markup["message_id"],
+        edits.last().unwrap().json()["message_id"]
+    );
+
+    let receipts = broker.receipts();
+    let receipt: DeliveryReceipt = serde_json::from_slice(&receipts[4].payload).unwrap();
+    assert_eq!(receipt.message_ids.len(), 2);
+    assert_eq!(receipt.message_ids[0], 1000);
+}
+
+#[tokio::test]
+async fn failed_stream_edits_are_not_resent_as_plain_text() {
+    let harness = Harness::start_with(|ctx| {
+        ctx.with_rate_limits(RateLimitOptions {
+            enabled: false,
+            ..RateLimitOptions::default()
+        })
+    })
+    .await;
+    let Harness { telegram, broker } = &harness;
+
+    harness
+        .send(json!({ "type": "StreamMessage", "data": {
+            "stream_id": "answer-1",
+            "action": "start",
+            "text": "**Hello**",
+            "parse_mode": "Markdown"
+        } }))
+        .await;
+    harness.wait_for_settlements(1).await;
+    telegram.respond_once(
+        "editMessageText",
+        json!({ "ok": false, "error_code": 400, "description": "Bad Request: message to edit not found" }),
+    );
+    harness
+        .send(json!({ "type": "StreamMessage", "data": {
+            "stream_id": "answer-1",
+            "action": "finish",
+            "text": " world"
+        } }))
+        .await;
+
+    harness.wait_for_settlements(2).await;
+    assert_eq!(telegram.requests_for("editMessageText").len(), 1);
+    assert_eq!(broker.dead_letters().len(), 1);
+}
+
+#[tokio::test]
+async fn long_texts_and_captions_are_split() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    let buttons = json!([[{ "text": "More", "callback_data": "more" }]]);
+    let paragraph = format!("**{}**\n\n", "word ".repeat(200));
+    for message_type in [
+        json!({ "type": "TextMessage", "data": {
+            "text": paragraph.repeat(5),
+            "parse_mode": "Markdown",
+            "buttons": buttons,
+            "reply_to_message_id": 7
+        } }),
+        json!({ "type": "ImageMessage", "data": {
+            "image": { "type": "file_id", "file_id": "AgAC" },
+            "caption": paragraph.repeat(2),
+            "buttons": buttons
+        } }),
+    ] {
+        harness.send(message_type).await;
+    }
+
+    harness.wait_for_settlements(2).await;
+    let texts = telegram.requests_for("sendMessage");
+    assert_eq!(texts.len(), 3);
+    let (parts, overflow) = texts.split_at(2);
+    for (i, part) in parts.iter().enumerate() {
+        let part = part.json();
+        let text = part["text"].as_str().unwrap();
+        assert!(text.starts_with("<b>word"));
+        assert!(text.encode_utf16().count() <= 4096);
+        assert_eq!(part["reply_parameters"].is_object(), i == 0);
+        assert_eq!(part["reply_markup"].is_object(), i == 1);
+    }
+
+    // sendPhoto is a multipart request.
+    let photo = telegram.wait_for("sendPhoto").await;
+    assert!(
+        photo
+            .body
+            .contains(&format!("<b>{}</b>", "word ".repeat(200)))
+    );
+    assert!(!photo.body.contains("reply_markup"));
+    let overflow = overflow[0].json();
+    assert!(overflow["text"].as_str().unwrap().starts_with("<b>word"));
+    assert_eq!(overflow["parse_mode"], "HTML");
+    assert!(overflow["reply_markup"]["inline_keyboard"].is_array());
+
+    let receipts = broker.receipts();
+    let receipt: DeliveryReceipt = serde_json::from_slice(&receipts[1].payload).unwrap();
+    assert_eq!(receipt.message_ids.len(), 2);
+}
+
+#[tokio::test]
+async fn failed_parts_report_the_parts_already_sent() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    let bad_request =
+        json!({ "ok": false, "error_code": 400, "description": "Bad Request: chat not found" });
+    telegram.respond_once(
+        "sendMessage",
+        json!({ "ok": true, "result": {
+            "message_id": 900,
+            "date": 1_700_000_000,
+            "chat": { "id": 42, "type": "private", "first_name": "Test" },
+            "text": "ok"
+        } }),
+    );
+    telegram.respond_once("sendMessage", bad_request.clone());
+    telegram.respond_once("sendMessage", bad_request);
+    let paragraph = format!("{}\n\n", "word ".repeat(200));
+    harness
+        .send(json!({ "type": "TextMessage", "data": { "text": paragraph.repeat(5) } }))
+        .await;
+    harness
+        .send(json!({ "type": "ImageMessage", "data": {
+            "image": { "type": "file_id", "file_id": "AgAC" },
+            "caption": paragraph.repeat(2)
+        } }))
+        .await;
+
+    harness.wait_for_settlements(2).await;
+    assert_eq!(telegram.requests_for("sendMessage").len(), 3);
+    let receipts = broker.receipts();
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 2);
+    for (i, sent) in [900, 1000].into_iter().enumerate() {
+        let receipt: DeliveryReceipt = serde_json::from_slice(&receipts[i].payload).unwrap();
+        assert_eq!(receipt.status, DeliveryStatus::Failed);
+        assert_eq!(receipt.message_ids, vec![sent]);
+        let record: DeadLetterRecord = serde_json::from_slice(&dead_letters[i].payload).unwrap();
+        assert_eq!(record.error_kind, ErrorKind::TelegramApi);
+        assert_eq!(record.sent_message_ids, vec![sent]);
+    }
+}
+
+#[tokio::test]
+async fn reply_markup_removes_keyboards_and_forces_replies() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    for data in [
+        json!({ "text": "Keyboard hidden", "reply_markup": { "type": "remove" } }),
+        json!({ "text": "Your name?", "reply_markup": {
+            "type": "force_reply",
+            "input_field_placeholder": "Name"
+        } }),
+        json!({
+            "text": "Both",
+            "buttons": [[{ "text": "A", "callback_data": "a" }]],
+            "reply_keyboard": { "keyboard": [[{ "text": "B" }]] }
+        }),
+    ] {
+        harness
+            .send(json!({ "type": "TextMessage", "data": data }))
+            .await;
+    }
+
+    harness.wait_for_settlements(3).await;
+    let sent = telegram.requests_for("sendMessage");
+    assert_eq!(sent.len(), 2);
+    assert_eq!(
+        sent[0].json()["reply_markup"],
+        json!({ "remove_keyboard": true })
+    );
+    let force_reply = &sent[1].json()["reply_markup"];
+    assert_eq!(force_reply["force_reply"], true);
+    assert_eq!(force_reply["input_field_placeholder"], "Name");
+
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    let record: DeadLetterRecord = serde_json::from_slice(&dead_letters[0].payload).unwrap();
+    assert!(record.error_message.contains("Only one of reply_markup"));
+}
+
+#[tokio::test]
+async fn copy_text_buttons_are_sent_as_raw_markup() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    let buttons = json!([
+        [{ "text": "Copy", "copy_text": { "text": "cargo build" } }],
+        [{ "text": "Done", "callback_data": "done" }]
+    ]);
+    for message_type in [
+        json!({ "type": "TextMessage", "data": { "text": "Run this", "buttons": buttons } }),
+        json!({ "type": "ImageMessage", "data": {
+            "image": { "type": "file_id", "file_id": "AgAC" },
+            "buttons": buttons
+        } }),
+        json!({ "type": "EditMessage", "data": { "message_id": 900, "new_buttons": buttons } }),
+    ] {
+        harness.send(message_type).await;
+    }
+
+    harness.wait_for_settlements(3).await;
+    let keyboard = json!({ "inline_keyboard": [
+        [{ "text": "Copy", "copy_text": { "text": "cargo build" } }],
+        [{ "text": "Done", "callback_data": "done" }]
+    ] });
+    let text = telegram.wait_for("sendMessage").await.json();
+    assert_eq!(text["text"], "Run this");
+    assert_eq!(text["chat_id"], 42);
+    assert_eq!(text["reply_markup"], keyboard);
+
+    // Media is uploaded as multipart, so the keyboard follows in an edit.
+    let photo = telegram.wait_for("sendPhoto").await;
+    assert!(!photo.body.contains("reply_markup"));
+    let edits = telegram.requests_for("editMessageReplyMarkup");
+    assert_eq!(edits.len(), 2);
+    assert_eq!(edits[0].json()["message_id"], 1001);
+    assert_eq!(edits[0].json()["reply_markup"], keyboard);
+    assert_eq!(edits[1].json()["message_id"], 900);
+    assert_eq!(edits[1].json()["reply_markup"], keyboard);
+
+    let receipts = broker.receipts();
+    let receipt: DeliveryReceipt = serde_json::from_slice(&receipts[1].payload).unwrap();
+    assert_eq!(receipt.status, DeliveryStatus::Delivered);
+    assert_eq!(receipt.message_ids, vec![1001]);
+}
+
+#[tokio::test]
+async fn callback_queries_are_auto_answered_only_without_a_backend_answer() {
+    let answers = Arc::new(CallbackAnswers::new(CallbackOptions {
+        auto_answer: AutoAnswer::Timeout,
+        answer_timeout_ms: 300,
+    }));
+    let harness = Harness::start_with(|ctx| ctx.with_callback_answers(answers.clone())).await;
+    let Harness { telegram, broker } = &harness;
+
+    for id in ["cbq-1", "cbq-2"] {
+        callback_query_handler(
+            telegram.bot(),
+            callback_query(id),
+            broker.clone(),
+            open_auth(),
+            answers.clone(),
+        )
+        .await
+        .unwrap();
+    }
+    harness
+        .send(json!({ "type": "AnswerCallbackQuery", "data": {
+            "callback_query_id": "cbq-1",
+            "text": "Saved",
+            "show_alert": true
+        } }))
+        .await;
+
+    let answered = telegram.wait_for("answerCallbackQuery").await.json();
+    assert_eq!(answered["callback_query_id"], "cbq-1");
+    assert_eq!(answered["text"], "Saved");
+    assert_eq!(answered["show_alert"], true);
+
+    // Only the query the backend left alone is answered after the timeout.
+    let answers = telegram.wait_for_n("answerCallbackQuery", 2).await;
+    let auto_answered = answers[1].json();
+    assert_eq!(auto_answered["callback_query_id"], "cbq-2");
+    assert!(auto_answered.get("text").is_none());
+    tokio::time::sleep(Duration::from_millis(400)).await;
+    assert_eq!(telegram.requests_for("answerCallbackQuery").len(), 2);
+
+    // A late answer would be rejected by Telegram, so it is dropped instead.
+    harness
+        .send(json!({ "type": "AnswerCallbackQuery", "data": {
+            "callback_query_id": "cbq-2",
+            "text": "Too late"
+        } }))
+        .await;
+    let settlements = harness.wait_for_settlements(2).await;
+    assert!(matches!(settlements[1], Settlement::Acked(_)));
+    assert_eq!(telegram.requests_for("answerCallbackQuery").len(), 2);
+    assert!(broker.dead_letters().is_empty());
+}
+
+#[tokio::test]
+async fn chat_actions_are_kept_alive_until_the_reply() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+    tokio::time::pause();
+
+    harness
+        .send(json!({ "type": "TypingMessage", "data": { "action": "choose_sticker" } }))
+        .await;
+    harness
+        .send(json!({ "type": "TypingMessage", "data": {
+            "action": "upload_photo",
+            "until_reply": true
+        } }))
+        .await;
+
+    // The action is refreshed before Telegram clears it after five seconds.
+    let actions = telegram.wait_for_n("sendChatAction", 3).await;
+    assert_eq!(actions[0].json()["action"], "choose_sticker");
+    assert!(
+        actions[1..]
+            .iter()
+            .all(|a| a.json()["action"] == "upload_photo")
+    );
+
+    harness
+        .send(json!({ "type": "TextMessage", "data": { "text": "Here it is" } }))
+        .await;
+    harness.wait_for_settlements(3).await;
+    tokio::time::advance(Duration::from_secs(10)).await;
+    assert_eq!(telegram.requests_for("sendChatAction").len(), 3);
+    assert!(broker.dead_letters().is_empty());
+}
+
+#[tokio::test]
+async fn edits_follow_the_formatting_rules_of_sends() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    for message_type in [
+        json!({ "type": "EditMessage", "data": {
+            "message_id": 7, "new_text": "**done**", "parse_mode": "Markdown"
+        } }),
+        json!({ "type": "EditMessage", "data": { "message_id": 8, "new_text": "**done**" } }),
+        json!({ "type": "EditCaption", "data": {
+            "message_id": 9, "caption": "_new_", "parse_mode": "Markdown"
+        } }),
+        json!({ "type": "EditMedia", "data": {
+            "message_id": 10,
+            "media": { "type": "photo", "media": { "type": "url", "url": "https://example.com/v2.png" } },
+            "buttons": [[{ "text": "Again", "callback_data": "regenerate" }]]
+        } }),
+        json!({ "type": "EditLiveLocation", "data": {
+            "message_id": 11, "latitude": 59.33, "longitude": 18.06, "heading": 90
+        } }),
+    ] {
+        harness.send(message_type).await;
+    }
+
+    let settlements = harness.wait_for_settlements(5).await;
+    assert!(
+        settlements
+            .iter()
+            .all(|s| matches!(s, Settlement::Acked(_)))
+    );
+    assert!(broker.dead_letters().is_empty());
+
+    let texts = telegram.requests_for("editMessageText");
+    assert_eq!(texts[0].json()["parse_mode"], "HTML");
+    assert_eq!(texts[0].json()["text"], "<b>done</b>");
+    // Without a parse mode the text is sent as is.
+    assert_eq!(texts[1].json()["text"], "**done**");
+    assert!(texts[1].json().get("parse_mode").is_none());
+
+    let caption = telegram.wait_for("editMessageCaption").await.json();
+    assert_eq!(caption["parse_mode"], "HTML");
+    assert_eq!(caption["caption"], "<i>new</i>");
+
+    let media = telegram.wait_for("editMessageMedia").await;
+    assert!(media.body.contains("https://example.com/v2.png"));
+    assert!(media.body.contains("regenerate"));
+
+    let location = telegram.wait_for("editMessageLiveLocation").await.json();
+    assert_eq!(location["message_id"], 11);
+    assert_eq!(location["heading"], 90);
+
+    // Like sends, edits Telegram cannot parse fall back to the rendered text
+    // without its tags, not to the Markdown source.
+    let entity_error = json!({
+        "ok": false,
+        "error_code": 400,
+        "description": "Bad Request: can't parse entities: Unsupported start tag \"x\" at byte offset 0"
+    });
+    telegram.respond_once("editMessageText", entity_error.clone());
+    telegram.respond_once("editMessageCaption", entity_error);
+    harness
+        .send(json!({ "type": "EditMessage", "data": {
+            "message_id": 7, "new_text": "**done**", "parse_mode": "Markdown"
+        } }))
+        .await;
+    harness
+        .send(json!({ "type": "EditCaption", "data": {
+            "message_id": 9, "caption": "_new_", "parse_mode": "Markdown"
+        } }))
+        .await;
+    harness.wait_for_settlements(7).await;
+    assert!(broker.dead_letters().is_empty());
+
+    let texts = telegram.requests_for("editMessageText");
+    assert_eq!(texts.len(), 4);
+    assert_eq!(texts[3].json()["text"], "done");
+    assert!(texts[3].json().get("parse_mode").is_none());
+    let captions = telegram.requests_for("editMessageCaption");
+    assert_eq!(captions.len(), 3);
+    assert_eq!(captions[2].json()["caption"], "new");
+    assert!(captions[2].json().get("parse_mode").is_none());
+}
+
+#[tokio::test]
+async fn messages_are_forwarded_copied_and_pinned() {
+    let harness = Harness::start_with(|ctx| {
+        ctx.with_rate_limits(RateLimitOptions {
+            enabled: false,
+            ..RateLimitOptions::default()
+        })
+    })
+    .await;
+    let Harness { telegram, broker } = &harness;
+    telegram.respond_once(
+        "copyMessage",
+        json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: can't parse entities: Unsupported start tag \"x\" at byte offset 0"
+        }),
+    );
+    for message_type in [
+        json!({ "type": "ForwardMessage", "data": { "from_chat_id": -100, "message_id": 5 } }),
+        json!({ "type": "CopyMessage", "data": {
+            "from_chat_id": -100,
+            "message_id": 6,
+            "caption": "**Curated**",
+            "parse_mode": "Markdown",
+            "reply_to_message_id": 3
+        } }),
+        json!({ "type": "CopyMessages", "data": {
+            "from_chat_id": -100, "message_ids": [7, 8], "remove_caption": true
+        } }),
+        json!({ "type": "PinChatMessage", "data": { "message_id": 9, "disable_notification": true } }),
+        json!({ "type": "UnpinChatMessage", "data": { "message_id": 9 } }),
+        json!({ "type": "UnpinAllChatMessages", "data": {} }),
+    ] {
+        harness.send_to(42, Some(12), message_type).await;
+    }
+
+    harness.wait_for_settlements(6).await;
+    let message_ids: Vec<Vec<i32>> = broker
+        .receipts()
+        .iter()
+        .map(|published| {
+            let receipt: DeliveryReceipt = serde_json::from_slice(&published.payload).unwrap();
+            assert_eq!(receipt.status, DeliveryStatus::Delivered, "{receipt:?}");
+            receipt.message_ids
+        })
+        .collect();
+    assert_eq!(
+        message_ids,
+        vec![
+            vec![1000],
+            vec![1001],
+            vec![1002, 1003],
+            vec![9],
+            vec![9],
+            vec![]
+        ]
+    );
+
+    let forward = telegram.wait_for("forwardMessage").await.json();
+    assert_eq!(forward["from_chat_id"], -100);
+    assert_eq!(forward["message_thread_id"], 12);
+
+    let copies = telegram.requests_for("copyMessage");
+    let copy = copies[0].json();
+    assert_eq!(copy["caption"], "<b>Curated</b>");
+    assert_eq!(copy["parse_mode"], "HTML");
+    assert_eq!(copy["reply_parameters"]["message_id"], 3);
+    // A caption Telegram cannot parse is resent without its tags.
+    let fallback = copies[1].json();
+    assert_eq!(fallback["caption"], "Curated");
+    assert!(fallback.get("parse_mode").is_none());
+
+    let copies = telegram.wait_for("copyMessages").await.json();
+    assert_eq!(copies["message_ids"], json!([7, 8]));
+    assert_eq!(copies["remove_caption"], true);
+
+    let pin = telegram.wait_for("pinChatMessage").await.json();
+    assert_eq!(pin["disable_notification"], true);
+    telegram.wait_for("unpinChatMessage").await;
+    telegram.wait_for("unpinAllChatMessages").await;
+}
+
+#[tokio::test]
+async fn bot_reactions_use_the_incoming_reaction_strings() {
+    let harness = Harness::start().await;
+    let Harness { telegram, broker } = &harness;
+
+    for reaction in [json!(["👀", "custom:5368324170671202286"]), json!(["paid"])] {
+        harness
+            .send(json!({ "type": "SetReaction", "data": {
+                "message_id": 7, "reaction": reaction, "is_big": true
+            } }))
+            .await;
+    }
+
+    harness.wait_for_settlements(2).await;
+    let requests = telegram.requests_for("setMessageReaction");
+    assert_eq!(requests.len(), 1);
+    let request = requests[0].json();
+    assert_eq!(request["message_id"], 7);
+    assert_eq!(request["is_big"], true);
+    assert_eq!(
+        request["reaction"],
+        json!([
+            { "type": "emoji", "emoji": "👀" },
+            { "type": "custom_emoji", "custom_emoji_id": "5368324170671202286" }
+        ])
+    );
+
+    let dead_letters = broker.dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    let record: DeadLetterRecord = serde_json::from_slice(&dead_letters[0].payload).unwrap();
+    assert!(record.error_message.contains("paid reactions"));
+}
+
+#[tokio::test]
+async fn join_requests_and_member_updates_support_moderation() {
+    let harness = Harness::start_with(|ctx| {
+        ctx.with_rate_limits(RateLimitOptions {
+            enabled: false,
+            ..RateLimitOptions::default()
+        })
+    })
+    .await;
+    let Harness { telegram, broker } = &harness;
+    let mut incoming = broker.incoming();
+
+    let group = json!({ "id": -100200, "type": "supergroup", "title": "Community" });
+    let user = json!({ "id": 77, "is_bot": false, "first_name": "Bob", "username": "bob" });
+    let request: ChatJoinRequest = serde_json::from_value(json!({
+        "chat": group,
+        "from": user,
+        "user_chat_id": 77,
+        "date": 1_700_000_000,
+        "bio": "Hi",
+        "invite_link": {
+            "invite_link": "https://t.me/+abc",
+            "creator": { "id": 1, "is_bot": false, "first_name": "Admin" },
+            "creates_join_request": true,
+            "is_primary": false,
+            "is_revoked": false
+        }
+    }))
+    .unwrap();
+    chat_join_request_handler(request, broker.clone())
+        .await
+        .unwrap();
+
+    let published = incoming.recv().await.unwrap();
+    assert_eq!(published.key.as_deref(), Some("77"));
+    let message: IncomingMessage = serde_json::from_slice(&published.payload).unwrap();
+    match message.message_type {
+        IncomingMessageType::ChatJoinRequest(data) => {
+            assert_eq!(data.chat_id, -100200);
+            assert_eq!(data.user_id, 77);
+            assert_eq!(data.bio.as_deref(), Some("Hi"));
+            assert_eq!(data.invite_link.as_deref(), Some("https://t.me/+abc"));
+        }
+        other => panic!("expected ChatJoinRequest, got {other:?}"),
+    }
+
+    let update: ChatMemberUpdated = serde_json::from_value(json!({
+        "chat": group,
+        "from": { "id": 1, "is_bot": false, "first_name": "Admin" },
+        "date": 1_700_000_100,
+        "old_chat_member": { "status": "left", "user": user },
+        "new_chat_member": { "status": "member", "user": user },
+        "via_join_request": true
+    }))
+    .unwrap();
+    chat_member_handler(update, broker.clone()).await.unwrap();
+
+    let published = incoming.recv().await.unwrap();
+    let message: IncomingMessage = serde_json::from_slice(&published.payload).unwrap();
+    match message.message_type {
+        IncomingMessageType::ChatMember(data) => {
+            assert_eq!(data.user_id, 77);
+            assert_eq!(data.changed_by, 1);
+            assert_eq!(
+                (data.old_status.as_str(), data.new_status.as_str()),
+                ("left", "member")
+            );
+            assert!(data.is_member);
+            assert!(data.via_join_request);
+        }
+        other => panic!("expected ChatMember, got {other:?}"),
+    }
+
+    for message_type in [
+        json!({ "type": "ApproveChatJoinRequest", "data": { "user_id": 77 } }),
+        json!({ "type": "RestrictChatMember", "data": {
+            "user_id": 77,
+            "permissions": { "can_send_messages": true },
+            "until_date": "2030-01-01T00:00:00Z"
+        } }),
+        json!({ "type": "PromoteChatMember", "data": { "user_id": 78, "can_pin_messages": true } }),
+        json!({ "type": "BanChatMember", "data": { "user_id": 79, "revoke_messages": true } }),
+        json!({ "type": "UnbanChatMember", "data": { "user_id": 79, "only_if_banned": true } }),
+        json!({ "type": "DeclineChatJoinRequest", "data": { "user_id": 80 } }),
+    ] {
+        harness.send_to(-100200, None, message_type).await;
+    }
+
+    let settlements = harness.wait_for_settlements(6).await;
+    assert!(
+        settlements
+            .iter()
+            .all(|s| matches!(s, Settlement::Acked(_)))
+    );
+    assert!(broker.dead_letters().is_empty());
+
+    let approve = telegram.wait_for("approveChatJoinRequest").await.json();
+    assert_eq!(approve["chat_id"], -100200);
+    assert_eq!(approve["user_id"], 77);
+
+    let restrict = telegram.wait_for("restrictChatMember").await.json();
+    assert_eq!(restrict["permissions"]["can_send_messages"], true);
+    assert_ne!(restrict["permissions"]["can_send_photos"], true);
+    assert_eq!(restrict["until_date"], 1_893_456_000);
+
+    let promote = telegram.wait_for("promoteChatMember").await.json();
+    assert_eq!(promote["can_pin_messages"], true);
+    assert!(promote.get("can_promote_members").is_none());
+
+    let ban = telegram.wait_for("banChatMember").await.json();
+    assert_eq!(ban["revoke_messages"], true);
+    telegram.wait_for("unbanChatMember").await;
+    telegram.wait_for("declineChatJoinRequest").await;
+}